@@ -0,0 +1,1310 @@
+use crate::build_system::{
+    BUCK2_BUILD_FILES, GRADLE_BUILD_FILES, MAVEN_BUILD_FILES, PANTS_BUILD_FILES,
+    detect_build_system,
+};
+use crate::fs_util::{
+    available_space, is_contained, make_executable, make_writable, move_path, remove_path,
+};
+use anyhow::{Context, Result};
+use bzip2::read::BzDecoder;
+use clap::ValueEnum;
+use flate2::read::GzDecoder;
+use glob::Pattern;
+use log::{debug, info, warn};
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Cursor, Read, Seek, copy};
+use std::path::{Path, PathBuf};
+use tar::{Archive, EntryType};
+use xz2::read::XzDecoder;
+use zip::ZipArchive;
+
+/// Archive formats the project source can be downloaded in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ArchiveFormat {
+    #[value(name = "tar.gz")]
+    TarGz,
+    Zip,
+    #[value(name = "tar.xz")]
+    TarXz,
+    #[value(name = "tar.bz2")]
+    TarBz2,
+    Tar,
+}
+
+impl ArchiveFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarXz => "tar.xz",
+            ArchiveFormat::TarBz2 => "tar.bz2",
+            ArchiveFormat::Tar => "tar",
+        }
+    }
+}
+
+impl fmt::Display for ArchiveFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+/// A downloaded archive, saved in the work directory or, if it is small enough,
+/// kept in memory (see `--in-memory-limit`).
+#[derive(Clone, Copy)]
+pub(crate) enum ArchiveSource<'a> {
+    File(&'a Path),
+    Memory(&'a [u8]),
+}
+
+/// Reader over an [`ArchiveSource`]; zip archives need to seek.
+pub(crate) trait ArchiveReader: Read + Seek {}
+
+impl<T: Read + Seek> ArchiveReader for T {}
+
+impl<'a> ArchiveSource<'a> {
+    pub(crate) fn open(self) -> Result<Box<dyn ArchiveReader + 'a>> {
+        match self {
+            ArchiveSource::File(path) => Ok(Box::new(
+                File::open(path).context("Failed to open archive file")?,
+            )),
+            ArchiveSource::Memory(contents) => Ok(Box::new(Cursor::new(contents))),
+        }
+    }
+
+    /// Size of the archive in bytes.
+    pub(crate) fn size(self) -> Result<u64> {
+        match self {
+            ArchiveSource::File(path) => Ok(fs::metadata(path)
+                .context("Failed to stat archive file")?
+                .len()),
+            ArchiveSource::Memory(contents) => Ok(contents.len() as u64),
+        }
+    }
+
+    /// Removes the downloaded file once its contents were extracted.
+    fn remove(self) -> Result<()> {
+        match self {
+            ArchiveSource::File(path) => {
+                fs::remove_file(path).context("Failed to remove downloaded archive")
+            }
+            ArchiveSource::Memory(_) => Ok(()),
+        }
+    }
+}
+
+/// Detects the archive format from the extension of a file name or URL path.
+pub(crate) fn detect_archive_format(name: &str) -> Option<ArchiveFormat> {
+    let name = name.to_ascii_lowercase();
+    if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(ArchiveFormat::TarGz)
+    } else if name.ends_with(".zip") {
+        Some(ArchiveFormat::Zip)
+    } else if name.ends_with(".tar.xz") {
+        Some(ArchiveFormat::TarXz)
+    } else if name.ends_with(".tar.bz2") {
+        Some(ArchiveFormat::TarBz2)
+    } else if name.ends_with(".tar") {
+        Some(ArchiveFormat::Tar)
+    } else {
+        None
+    }
+}
+
+/// Strips a known archive extension from a file name, e.g. `app.tar.gz` becomes `app`.
+pub(crate) fn strip_archive_extension(name: &str) -> &str {
+    [".tar.gz", ".tgz", ".zip", ".tar.xz", ".tar.bz2", ".tar"]
+        .iter()
+        .find_map(|extension| {
+            let stem = name.len().checked_sub(extension.len())?;
+            (name.is_char_boundary(stem) && name[stem..].eq_ignore_ascii_case(extension))
+                .then(|| &name[..stem])
+        })
+        .unwrap_or(name)
+}
+
+/// Detects the archive format from the magic bytes at the start of the file.
+/// Callers fall back to the extension with [`detect_archive_format`].
+pub(crate) fn detect_format_from_magic(archive_path: &Path) -> Result<Option<ArchiveFormat>> {
+    let mut header = Vec::with_capacity(512);
+    File::open(archive_path)
+        .context("Failed to open archive file")?
+        .take(512)
+        .read_to_end(&mut header)
+        .context("Failed to read archive header")?;
+    Ok(sniff_format(&header))
+}
+
+/// Identifies the archive format from the first (up to 512) bytes of a file.
+///
+/// Gzip is always reported as [`ArchiveFormat::TarGz`], even though it may
+/// hold a single file, see [`is_gzipped_tar`]. Only empty zip archives start
+/// with the end of central directory record `PK\x05\x06`.
+pub(crate) fn sniff_format(header: &[u8]) -> Option<ArchiveFormat> {
+    if header.starts_with(&[0x1f, 0x8b]) {
+        Some(ArchiveFormat::TarGz)
+    } else if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+        Some(ArchiveFormat::Zip)
+    } else if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        Some(ArchiveFormat::TarXz)
+    } else if header.starts_with(b"BZh") {
+        Some(ArchiveFormat::TarBz2)
+    } else if is_tar_header(header) {
+        Some(ArchiveFormat::Tar)
+    } else {
+        None
+    }
+}
+
+/// How many archives nested in each other `--unwrap-nested` extracts at most.
+const MAX_NESTED_ARCHIVES: usize = 4;
+
+/// Extracts archives nested in the project, as long as its root contains no
+/// build files but a single archive (next to e.g. an install script).
+pub(crate) fn unwrap_nested_archives(project_root: &Path) -> Result<PathBuf> {
+    let mut project_root = project_root.to_path_buf();
+    for level in 1..=MAX_NESTED_ARCHIVES + 1 {
+        if detect_build_system(&project_root).is_some() {
+            break;
+        }
+
+        let archives: Vec<_> = fs::read_dir(&project_root)
+            .context("Failed to read project directory")?
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                let format = detect_archive_format(&path.file_name()?.to_string_lossy())?;
+                path.is_file().then_some((path, format))
+            })
+            .collect();
+        let [(archive_path, name_format)] = archives.as_slice() else {
+            break;
+        };
+        if level > MAX_NESTED_ARCHIVES {
+            anyhow::bail!(
+                "Archives are nested more than {} levels deep, giving up at {:?}",
+                MAX_NESTED_ARCHIVES,
+                archive_path
+            );
+        }
+
+        let format = detect_format_from_magic(archive_path)?.unwrap_or(*name_format);
+        info!(
+            "Unwrapping nested archive (level {}): {}",
+            level,
+            archive_path.display()
+        );
+        project_root = prepare_project(
+            ArchiveSource::File(archive_path),
+            format,
+            &[],
+            &project_root,
+            false,
+        )?;
+    }
+    Ok(project_root)
+}
+
+/// Extracts an archive of projects for `--batch` into `temp_dir_path` and
+/// returns its top-level entries, sorted by name: directories and archives
+/// each holding one project. A single wrapping folder is looked into.
+pub(crate) fn extract_batch(
+    archive: ArchiveSource,
+    format: ArchiveFormat,
+    extract_only: &[Pattern],
+    temp_dir_path: &Path,
+) -> Result<Vec<PathBuf>> {
+    info!(
+        "Extracting {} archive of projects to: {}",
+        format,
+        temp_dir_path.display()
+    );
+    extract_archive(archive, format, extract_only, temp_dir_path)
+        .context("Failed to extract archive")?;
+
+    let mut batch_root = temp_dir_path.to_path_buf();
+    loop {
+        let mut entries = fs::read_dir(&batch_root)
+            .context("Failed to read the extracted archive")?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to collect directory entries")?;
+        entries.sort();
+
+        match entries.as_slice() {
+            [folder] if folder.is_dir() && detect_build_system(folder).is_none() => {
+                batch_root = folder.clone();
+            }
+            _ => {
+                let projects: Vec<_> = entries
+                    .into_iter()
+                    .filter(|path| {
+                        let is_project = path.is_dir()
+                            || path.file_name().is_some_and(|name| {
+                                detect_archive_format(&name.to_string_lossy()).is_some()
+                            });
+                        if !is_project {
+                            info!(
+                                "Skipping {}: neither a directory nor an archive",
+                                path.display()
+                            );
+                        }
+                        is_project
+                    })
+                    .collect();
+                if projects.is_empty() {
+                    anyhow::bail!("The archive contains no project directories or archives");
+                }
+                return Ok(projects);
+            }
+        }
+    }
+}
+
+/// Returns the root of a project directory found in a `--batch` archive,
+/// looking into a single wrapping folder like extraction does.
+pub(crate) fn batch_project_root(project_dir: &Path) -> Result<PathBuf> {
+    let mut project_root = project_dir.to_path_buf();
+    while detect_build_system(&project_root).is_none() {
+        let entries = fs::read_dir(&project_root)
+            .context(format!("Failed to read {:?}", project_root))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to collect directory entries")?;
+        match entries.as_slice() {
+            [folder] if folder.is_dir() => project_root = folder.clone(),
+            _ => break,
+        }
+    }
+    Ok(project_root)
+}
+
+/// Extracts a project archive found in a `--batch` archive into `project_dir`,
+/// which must not exist yet, and returns the root directory of the project.
+pub(crate) fn extract_batch_project(archive_path: &Path, project_dir: &Path) -> Result<PathBuf> {
+    let name = archive_path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let name_format = detect_archive_format(&name)
+        .context(format!("{:?} is not a known archive format", archive_path))?;
+    let format = detect_format_from_magic(archive_path)?.unwrap_or(name_format);
+
+    fs::create_dir(project_dir).context(format!("Failed to create {:?}", project_dir))?;
+    // prepare_project removes the archive once extracted, so move it into
+    // the project directory first
+    let moved = project_dir.join(&*name);
+    fs::rename(archive_path, &moved)
+        .context(format!("Failed to move {:?} to {:?}", archive_path, moved))?;
+    prepare_project(ArchiveSource::File(&moved), format, &[], project_dir, false)
+}
+
+/// Number of entries moved out of the subfolder between two progress messages
+/// when flattening.
+const FLATTEN_PROGRESS_INTERVAL: usize = 500;
+
+/// Extracts the archive into `temp_dir_path` and flattens a single top-level
+/// subfolder, returning the root directory of the project. With
+/// `pick_latest_dir`, several versioned subfolders with build files resolve
+/// to the one with the highest version.
+pub(crate) fn prepare_project(
+    archive: ArchiveSource,
+    format: ArchiveFormat,
+    extract_only: &[Pattern],
+    temp_dir_path: &Path,
+    pick_latest_dir: bool,
+) -> Result<PathBuf> {
+    extract_project(archive, format, extract_only, temp_dir_path)?;
+
+    // Move contents from top-level subfolder to temp directory
+    let entries = fs::read_dir(temp_dir_path)
+        .context("Failed to read temp directory")?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to collect directory entries")?;
+
+    // Find directories
+    let subfolders: Vec<_> = entries
+        .iter()
+        .filter(|entry| entry.path().is_dir())
+        .collect();
+
+    // If there's a single subfolder, move its contents up, unless the build
+    // files are at the root already, as in archives without a top-level
+    // folder such as source JARs
+    if subfolders.len() == 1 && detect_build_system(temp_dir_path).is_none() {
+        let subfolder_path = &subfolders[0].path();
+        info!(
+            "Moving contents from subfolder: {}",
+            subfolder_path.display()
+        );
+
+        // Entries can't be moved out of a read-only directory
+        make_writable(subfolder_path)?;
+
+        // Move all contents from subfolder to temp directory
+        let subfolder_entries = fs::read_dir(subfolder_path).context("Failed to read subfolder")?;
+
+        let mut moved = 0;
+        for entry in subfolder_entries {
+            let entry = entry.context("Failed to read subfolder entry")?;
+            let source_path = entry.path();
+            let file_name = source_path.file_name().unwrap();
+            let target_path = temp_dir_path.join(file_name);
+
+            // Move the file/directory
+            move_path(&source_path, &target_path).context(format!(
+                "Failed to move {:?} to {:?}",
+                source_path, target_path
+            ))?;
+
+            moved += 1;
+            if moved % FLATTEN_PROGRESS_INTERVAL == 0 {
+                debug!("Moved {} entries so far", moved);
+            }
+        }
+
+        // Remove the empty subfolder
+        fs::remove_dir(subfolder_path).context("Failed to remove empty subfolder")?;
+        info!("Successfully moved {} entries and removed subfolder", moved);
+    } else if subfolders.len() > 1 && detect_build_system(temp_dir_path).is_none() {
+        // Archives may bundle e.g. docs/ next to the project, so build the
+        // one subfolder that contains build files
+        let projects: Vec<_> = subfolders
+            .iter()
+            .map(|entry| entry.path())
+            .filter(|path| detect_build_system(path).is_some())
+            .collect();
+        let latest = match projects.as_slice() {
+            [_, _, ..] if pick_latest_dir => latest_versioned_dir(&projects),
+            _ => None,
+        };
+        if let Some(latest) = latest {
+            info!("Archive successfully extracted");
+            info!(
+                "Using the subfolder with the highest version: {}",
+                latest.display()
+            );
+            return Ok(latest.to_path_buf());
+        }
+        match projects.as_slice() {
+            [project] => {
+                info!("Archive successfully extracted");
+                info!("Using subfolder with build files: {}", project.display());
+                return Ok(project.clone());
+            }
+            [] => anyhow::bail!(
+                "The archive has {} top-level folders and none of them contains build files",
+                subfolders.len()
+            ),
+            _ => anyhow::bail!(
+                "The archive has several top-level folders with build files, can't choose between {:?}",
+                projects
+            ),
+        }
+    }
+
+    info!("Archive successfully extracted");
+    Ok(temp_dir_path.to_path_buf())
+}
+
+/// Returns the version in a directory name such as `app-1.2` or `app_v1.2.0`:
+/// the rest of the name from the start or the first `-` or `_` that is
+/// followed by a digit, optionally after a `v`.
+fn dir_version(name: &str) -> Option<&str> {
+    std::iter::once(0)
+        .chain(name.match_indices(['-', '_']).map(|(index, _)| index + 1))
+        .map(|start| &name[start..])
+        .map(|rest| rest.strip_prefix('v').unwrap_or(rest))
+        .find(|rest| rest.starts_with(|c: char| c.is_ascii_digit()))
+}
+
+/// Parses a directory's version as a semantic version, padding versions with
+/// fewer than three components, so that `1.2-SNAPSHOT` becomes
+/// `1.2.0-SNAPSHOT`.
+fn parse_semver(version: &str) -> Option<semver::Version> {
+    let (core, suffix) = version.split_at(version.find(['-', '+']).unwrap_or(version.len()));
+    let missing = 3usize.saturating_sub(core.split('.').count());
+    semver::Version::parse(&format!("{}{}{}", core, ".0".repeat(missing), suffix)).ok()
+}
+
+/// Picks the directory with the highest version among `dirs`, if all of them
+/// have one. Versions are compared as semantic versions, or by their text
+/// if any of them isn't one.
+fn latest_versioned_dir(dirs: &[PathBuf]) -> Option<&Path> {
+    let versions = dirs
+        .iter()
+        .map(|dir| Some((dir.as_path(), dir_version(dir.file_name()?.to_str()?)?)))
+        .collect::<Option<Vec<_>>>()?;
+    let semvers = versions
+        .iter()
+        .map(|&(dir, version)| Some((dir, parse_semver(version)?)))
+        .collect::<Option<Vec<_>>>();
+    match semvers {
+        Some(semvers) => semvers
+            .into_iter()
+            .max_by(|(_, a), (_, b)| a.cmp(b))
+            .map(|(dir, _)| dir),
+        None => {
+            debug!("Not all folder versions are semantic versions, comparing them as text");
+            versions
+                .into_iter()
+                .max_by_key(|&(_, version)| version)
+                .map(|(dir, _)| dir)
+        }
+    }
+}
+
+/// How many times the size of an archive extracting it is expected to take.
+const EXTRACTED_SIZE_FACTOR: u64 = 3;
+
+/// Checks that the filesystem of `dest_path` has room for the extracted
+/// archive, which is estimated at [`EXTRACTED_SIZE_FACTOR`] times its size.
+/// Fails if not even the size of the archive is available, since extraction
+/// would then run out of space partway, and warns below the estimate.
+pub(crate) fn check_disk_space(archive: ArchiveSource, dest_path: &Path) -> Result<()> {
+    let size = archive.size()?;
+    let available = available_space(dest_path)?;
+    let estimate = size.saturating_mul(EXTRACTED_SIZE_FACTOR);
+    if available < size {
+        anyhow::bail!(
+            "Insufficient disk space in {}: {:.1} MiB available, but extracting the {:.1} MiB archive needs about {:.1} MiB. Free up space or run monteur in a directory on a larger filesystem",
+            dest_path.display(),
+            to_mib(available),
+            to_mib(size),
+            to_mib(estimate)
+        );
+    }
+    if available < estimate {
+        warn!(
+            "Only {:.1} MiB of disk space available in {}, extracting the {:.1} MiB archive may need about {:.1} MiB",
+            to_mib(available),
+            dest_path.display(),
+            to_mib(size),
+            to_mib(estimate)
+        );
+    }
+    Ok(())
+}
+
+/// Converts a byte count to MiB.
+fn to_mib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+/// Checks whether a gzip file holds a tar archive, by looking for a valid tar
+/// header at the start of the decompressed stream.
+pub(crate) fn is_gzipped_tar(archive: ArchiveSource) -> Result<bool> {
+    let mut header = Vec::with_capacity(512);
+    GzDecoder::new(archive.open()?)
+        .take(512)
+        .read_to_end(&mut header)
+        .context("Failed to decompress archive")?;
+    Ok(is_tar_header(&header))
+}
+
+/// Checks the magic or, for pre-POSIX archives, the checksum of a tar header.
+fn is_tar_header(header: &[u8]) -> bool {
+    if header.len() < 512 {
+        return false;
+    }
+    if &header[257..262] == b"ustar" {
+        return true;
+    }
+    // The checksum is the sum of all header bytes, counting its own field as spaces
+    let stored = std::str::from_utf8(&header[148..156])
+        .ok()
+        .map(|field| field.trim_matches(|c: char| c == ' ' || c == '\0'))
+        .and_then(|field| u32::from_str_radix(field, 8).ok());
+    let computed: u32 = header
+        .iter()
+        .enumerate()
+        .map(|(index, byte)| {
+            if (148..156).contains(&index) {
+                u32::from(b' ')
+            } else {
+                u32::from(*byte)
+            }
+        })
+        .sum();
+    stored == Some(computed)
+}
+
+/// Decompresses a gzip file holding a single file rather than a tar archive
+/// into `dest_path`, named after `file_name` without its `.gz` extension.
+pub(crate) fn gunzip_single_file(
+    archive: ArchiveSource,
+    file_name: Option<&str>,
+    dest_path: &Path,
+) -> Result<PathBuf> {
+    let name = file_name
+        .map(|name| {
+            let stem = name.len().saturating_sub(3);
+            if name.is_char_boundary(stem) && name[stem..].eq_ignore_ascii_case(".gz") {
+                &name[..stem]
+            } else {
+                name
+            }
+        })
+        .filter(|name| !name.is_empty())
+        .unwrap_or("download");
+    let path = dest_path.join(name);
+    info!(
+        "The gzip file doesn't contain a tar archive, decompressing it as a single file: {}",
+        path.display()
+    );
+
+    // Decompressed next to it first, as the archive may have been saved under
+    // the same name
+    let partial_path = dest_path.join(".monteur-decompressing");
+    let mut decoder = GzDecoder::new(archive.open()?);
+    let mut file =
+        File::create(&partial_path).context(format!("Failed to create {:?}", partial_path))?;
+    copy(&mut decoder, &mut file).context("Failed to decompress archive")?;
+    archive.remove()?;
+    fs::rename(&partial_path, &path)
+        .context(format!("Failed to move {:?} to {:?}", partial_path, path))?;
+
+    // gzip doesn't record the file mode, so make scripts runnable again
+    let mut shebang = [0; 2];
+    let is_script = File::open(&path)
+        .and_then(|mut file| file.read_exact(&mut shebang))
+        .is_ok_and(|()| &shebang == b"#!");
+    if is_script {
+        make_executable(&path)?;
+    }
+    Ok(path)
+}
+
+/// Checks whether a zip archive downloaded as a `.jar` is a compiled JAR rather
+/// than a source bundle: it holds `.class` files, but no build files at its
+/// root or in a top-level directory.
+pub(crate) fn is_compiled_jar(archive: ArchiveSource) -> Result<bool> {
+    let zip = ZipArchive::new(archive.open()?).context("Failed to read zip archive")?;
+    let mut has_classes = false;
+    for name in zip.file_names() {
+        let path = Path::new(name);
+        if path.components().count() <= 2 && path.file_name().is_some_and(is_build_file) {
+            return Ok(false);
+        }
+        has_classes |= name.ends_with(".class");
+    }
+    Ok(has_classes)
+}
+
+/// Saves a downloaded file that needs no extraction into `dest_path` as
+/// `file_name`.
+pub(crate) fn save_single_file(
+    archive: ArchiveSource,
+    file_name: &str,
+    dest_path: &Path,
+) -> Result<PathBuf> {
+    let path = dest_path.join(file_name);
+    // The download was usually saved under that name already
+    if matches!(archive, ArchiveSource::File(archive_path) if archive_path == path) {
+        return Ok(path);
+    }
+    let mut file = File::create(&path).context(format!("Failed to create {:?}", path))?;
+    copy(&mut archive.open()?, &mut file).context(format!("Failed to write {:?}", path))?;
+    archive.remove()?;
+    Ok(path)
+}
+
+/// Extracts the archive into `temp_dir_path` as it is, without flattening,
+/// and removes it.
+pub(crate) fn extract_project(
+    archive: ArchiveSource,
+    format: ArchiveFormat,
+    extract_only: &[Pattern],
+    temp_dir_path: &Path,
+) -> Result<()> {
+    info!(
+        "Extracting {} archive to: {}",
+        format,
+        temp_dir_path.display()
+    );
+    extract_archive(archive, format, extract_only, temp_dir_path)
+        .context("Failed to extract archive")
+}
+
+/// Directory inside the destination that archives are extracted into first.
+const STAGING_DIR: &str = ".monteur-extracting";
+
+/// Extracts the archive into `dest_path` and removes it. If `extract_only` is
+/// non-empty, only the entries matching one of its patterns (plus the build
+/// files) are extracted.
+///
+/// The entries are extracted into a staging directory and only moved into
+/// place once the whole archive was read, so that a corrupt archive doesn't
+/// leave a partial tree behind that detection could misinterpret.
+fn extract_archive(
+    archive: ArchiveSource,
+    format: ArchiveFormat,
+    extract_only: &[Pattern],
+    dest_path: &Path,
+) -> Result<()> {
+    let staging_path = dest_path.join(STAGING_DIR);
+    if staging_path.exists() {
+        remove_path(&staging_path)?;
+    }
+    fs::create_dir(&staging_path).context(format!("Failed to create {:?}", staging_path))?;
+
+    if let Err(err) = unpack_archive(archive, format, extract_only, &staging_path) {
+        if let Err(cleanup_err) = remove_path(&staging_path) {
+            warn!(
+                "Failed to remove partially extracted archive: {:#}",
+                cleanup_err
+            );
+        }
+        return Err(err);
+    }
+
+    // The downloaded archive usually lives in `dest_path`, so remove it before
+    // the entries are moved there: an entry of the same name would otherwise
+    // be replaced by it, or removed along with it
+    archive.remove()?;
+    for entry in fs::read_dir(&staging_path).context("Failed to read extracted archive")? {
+        let source_path = entry.context("Failed to read extracted entry")?.path();
+        let target_path = dest_path.join(source_path.file_name().unwrap());
+        // Renames within the same directory tree, so this doesn't copy
+        move_path(&source_path, &target_path).context(format!(
+            "Failed to move {:?} to {:?}",
+            source_path, target_path
+        ))?;
+    }
+    fs::remove_dir(&staging_path).context(format!("Failed to remove {:?}", staging_path))?;
+    Ok(())
+}
+
+fn unpack_archive(
+    archive: ArchiveSource,
+    format: ArchiveFormat,
+    extract_only: &[Pattern],
+    dest_path: &Path,
+) -> Result<()> {
+    let file = archive.open()?;
+    match format {
+        ArchiveFormat::TarGz => unpack_tar(GzDecoder::new(file), extract_only, dest_path),
+        ArchiveFormat::TarXz => unpack_tar(XzDecoder::new(file), extract_only, dest_path),
+        ArchiveFormat::TarBz2 => unpack_tar(BzDecoder::new(file), extract_only, dest_path),
+        ArchiveFormat::Tar => unpack_tar(file, extract_only, dest_path),
+        ArchiveFormat::Zip => unpack_zip(file, extract_only, dest_path),
+    }
+}
+
+fn unpack_tar(reader: impl Read, extract_only: &[Pattern], dest_path: &Path) -> Result<()> {
+    let mut archive = Archive::new(reader);
+    // Like `Archive::unpack`, directories are unpacked last so that read-only
+    // directories don't prevent their contents from being written
+    let mut directories = Vec::new();
+    for entry in archive.entries().context("Failed to read archive")? {
+        let mut entry = entry.context("Failed to read archive entry")?;
+        let path = entry
+            .path()
+            .context("Failed to read archive entry path")?
+            .into_owned();
+        if !extract_only.is_empty() && !should_extract(&path, extract_only) {
+            continue;
+        }
+        match entry.header().entry_type() {
+            EntryType::Directory => directories.push(entry),
+            EntryType::Link => unpack_hardlink(&entry, &path, extract_only, dest_path)?,
+            _ => {
+                entry
+                    .unpack_in(dest_path)
+                    .context(format!("Failed to unpack {:?}", path))?;
+            }
+        }
+    }
+
+    // Subdirectories first, so that a parent is only made read-only once its
+    // children are done
+    for mut directory in directories.into_iter().rev() {
+        let path = directory
+            .path()
+            .context("Failed to read archive entry path")?
+            .into_owned();
+        directory
+            .unpack_in(dest_path)
+            .context(format!("Failed to unpack {:?}", path))?;
+    }
+    Ok(())
+}
+
+/// Creates the hardlink `path` to an earlier entry of the archive, or copies
+/// the linked file where the filesystem doesn't support hardlinks. A link to
+/// an entry that `extract_only` left out is skipped.
+fn unpack_hardlink<R: Read>(
+    entry: &tar::Entry<R>,
+    path: &Path,
+    extract_only: &[Pattern],
+    dest_path: &Path,
+) -> Result<()> {
+    let link_name = entry
+        .link_name()
+        .context(format!("Failed to read the target of hardlink {:?}", path))?
+        .with_context(|| format!("Hardlink {:?} has no target", path))?;
+    if !is_contained(path) || !is_contained(&link_name) {
+        anyhow::bail!(
+            "Hardlink {:?} to {:?} points outside the archive",
+            path,
+            link_name
+        );
+    }
+    if !extract_only.is_empty() && !should_extract(&link_name, extract_only) {
+        debug!(
+            "Skipping hardlink {:?}: its target {:?} wasn't extracted",
+            path, link_name
+        );
+        return Ok(());
+    }
+
+    // Unlike `Entry::unpack_in`, the lexical check above doesn't see symlinks
+    // unpacked earlier, so both ends of the link are resolved and checked
+    // before anything is created, replaced or linked
+    let root = dest_path
+        .canonicalize()
+        .context(format!("Failed to resolve {:?}", dest_path))?;
+    let source = dest_path.join(&link_name).canonicalize().context(format!(
+        "Failed to resolve the target of hardlink {:?}",
+        path
+    ))?;
+    if !source.starts_with(&root) {
+        anyhow::bail!(
+            "Hardlink {:?} to {:?} points outside the archive",
+            path,
+            link_name
+        );
+    }
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("Hardlink {:?} has no file name", path))?;
+    let parent = create_dir_inside(&root, path.parent().unwrap_or(Path::new("")))
+        .context(format!("Failed to unpack hardlink {:?}", path))?;
+    let target = parent.join(file_name);
+    if target.symlink_metadata().is_ok() {
+        remove_path(&target).context(format!("Failed to replace {:?}", target))?;
+    }
+    if let Err(err) = fs::hard_link(&source, &target) {
+        debug!(
+            "Failed to create hardlink {:?} to {:?} ({}), copying it instead",
+            path, link_name, err
+        );
+        fs::copy(&source, &target).context(format!(
+            "Failed to unpack hardlink {:?} to {:?}",
+            path, link_name
+        ))?;
+    }
+    Ok(())
+}
+
+/// Creates the directory `relative` below `root` one component at a time,
+/// refusing to follow a symlink out of `root`, and returns its resolved path.
+/// `root` must be canonical.
+fn create_dir_inside(root: &Path, relative: &Path) -> Result<PathBuf> {
+    let mut dir = root.to_path_buf();
+    for component in relative.components() {
+        let next = dir.join(component);
+        if next.symlink_metadata().is_err() {
+            fs::create_dir(&next).context(format!("Failed to create directory {:?}", next))?;
+        }
+        dir = next
+            .canonicalize()
+            .context(format!("Failed to resolve {:?}", next))?;
+        if !dir.starts_with(root) || !dir.is_dir() {
+            anyhow::bail!("{:?} is not a directory inside the archive", relative);
+        }
+    }
+    Ok(dir)
+}
+
+fn unpack_zip(file: impl Read + Seek, extract_only: &[Pattern], dest_path: &Path) -> Result<()> {
+    let mut archive = ZipArchive::new(file).context("Failed to read zip archive")?;
+    // Archives created on Windows may use `\` as separator, which the zip
+    // crate doesn't split into directories
+    let windows_paths = archive.file_names().any(|name| name.contains('\\'));
+    if extract_only.is_empty() && !windows_paths {
+        archive
+            .extract(dest_path)
+            .context("Failed to unpack zip archive")?;
+        return Ok(());
+    }
+    if windows_paths {
+        info!("The zip archive has Windows-style paths, converting them");
+    }
+
+    for index in 0..archive.len() {
+        let mut entry = archive
+            .by_index(index)
+            .context("Failed to read zip archive entry")?;
+        let is_dir = entry.is_dir() || entry.name().ends_with('\\');
+        // Entries escaping the destination directory are skipped
+        let path = if windows_paths {
+            windows_entry_path(entry.name())
+        } else {
+            entry.enclosed_name()
+        };
+        let Some(path) = path else {
+            continue;
+        };
+        if !extract_only.is_empty() && !should_extract(&path, extract_only) {
+            continue;
+        }
+
+        let target_path = dest_path.join(&path);
+        if is_dir {
+            fs::create_dir_all(&target_path)
+                .context(format!("Failed to create directory {:?}", target_path))?;
+            continue;
+        }
+        if let Some(parent) = target_path.parent() {
+            fs::create_dir_all(parent)
+                .context(format!("Failed to create directory {:?}", parent))?;
+        }
+        let mut target_file =
+            File::create(&target_path).context(format!("Failed to create {:?}", target_path))?;
+        copy(&mut entry, &mut target_file).context(format!("Failed to unpack {:?}", path))?;
+
+        #[cfg(unix)]
+        if let Some(mode) = entry.unix_mode() {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&target_path, fs::Permissions::from_mode(mode))
+                .context(format!("Failed to set permissions of {:?}", target_path))?;
+        }
+    }
+    Ok(())
+}
+
+/// Converts the name of a zip entry with Windows-style separators, such as
+/// `C:\project\pom.xml`, to a relative path with the drive letter stripped.
+/// Returns `None` if the entry would escape the destination directory.
+fn windows_entry_path(name: &str) -> Option<PathBuf> {
+    let name = name.replace('\\', "/");
+    let name = match name.as_bytes() {
+        [letter, b':', ..] if letter.is_ascii_alphabetic() => &name[2..],
+        _ => &name,
+    };
+    let path = Path::new(name.trim_start_matches('/'));
+    (is_contained(path) && path.components().next().is_some()).then(|| path.to_path_buf())
+}
+
+/// Returns whether an archive entry passes the `--extract-only` patterns.
+/// Patterns are matched against the entry path both with and without its
+/// top-level directory, since that one is flattened away later. Build files
+/// are always extracted so that detection keeps working.
+fn should_extract(path: &Path, extract_only: &[Pattern]) -> bool {
+    let mut components = path.components();
+    components.next();
+    let without_top_level = components.as_path();
+
+    let is_build_file = path.file_name().is_some_and(is_build_file);
+    let in_build_dir = [path, without_top_level]
+        .iter()
+        .any(|path| path.starts_with(".mvn") || path.starts_with("gradle"));
+
+    is_build_file
+        || in_build_dir
+        || extract_only
+            .iter()
+            .any(|pattern| pattern.matches_path(path) || pattern.matches_path(without_top_level))
+}
+
+/// Checks whether `name` is the name of a build file of any build system.
+fn is_build_file(name: &OsStr) -> bool {
+    MAVEN_BUILD_FILES
+        .iter()
+        .chain(GRADLE_BUILD_FILES.iter())
+        .chain(PANTS_BUILD_FILES.iter())
+        .chain(BUCK2_BUILD_FILES.iter())
+        .any(|build_file| name == *build_file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{self, Write};
+    use walkdir::WalkDir;
+    use zip::ZipWriter;
+    use zip::write::SimpleFileOptions;
+
+    /// Builds a `.tar.gz` of `(path, mode, contents)` entries; entries
+    /// without contents are directories.
+    fn tar_gz(entries: &[(&str, u32, Option<&[u8]>)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::default(),
+        ));
+        for (path, mode, contents) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(*mode);
+            match contents {
+                Some(contents) => {
+                    header.set_size(contents.len() as u64);
+                    builder.append_data(&mut header, path, *contents).unwrap();
+                }
+                None => {
+                    header.set_entry_type(EntryType::Directory);
+                    header.set_size(0);
+                    builder.append_data(&mut header, path, io::empty()).unwrap();
+                }
+            }
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn write_zip(path: &Path, entries: &[(&str, &[u8])]) {
+        let mut writer = ZipWriter::new(File::create(path).unwrap());
+        for (name, contents) in entries {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(contents).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn extract_keeps_entry_named_like_the_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("archive.zip");
+        write_zip(
+            &archive_path,
+            &[("pom.xml", b"<project/>"), ("archive.zip", b"inner")],
+        );
+
+        extract_project(
+            ArchiveSource::File(&archive_path),
+            ArchiveFormat::Zip,
+            &[],
+            dir.path(),
+        )
+        .unwrap();
+
+        assert_eq!(fs::read(&archive_path).unwrap(), b"inner");
+        assert_eq!(fs::read(dir.path().join("pom.xml")).unwrap(), b"<project/>");
+        assert!(!dir.path().join(STAGING_DIR).exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn prepare_project_flattens_read_only_entries() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let archive = tar_gz(&[
+            ("project/", 0o555, None),
+            ("project/pom.xml", 0o444, Some(b"<project/>")),
+        ]);
+
+        let project_root = prepare_project(
+            ArchiveSource::Memory(&archive),
+            ArchiveFormat::TarGz,
+            &[],
+            dir.path(),
+            false,
+        )
+        .unwrap();
+
+        let pom = dir.path().join("pom.xml");
+        assert_eq!(project_root, dir.path());
+        assert_eq!(fs::read(&pom).unwrap(), b"<project/>");
+        assert_eq!(
+            fs::metadata(&pom).unwrap().permissions().mode() & 0o777,
+            0o444
+        );
+        assert!(!dir.path().join("project").exists());
+    }
+
+    #[test]
+    fn tgz_is_detected_and_extracted_in_any_case() {
+        for name in ["app.tgz", "APP.TGZ", "https://example.com/dist/app-1.0.Tgz"] {
+            assert_eq!(detect_archive_format(name), Some(ArchiveFormat::TarGz));
+        }
+        assert_eq!(strip_archive_extension("APP.TGZ"), "APP");
+
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("APP.TGZ");
+        fs::write(
+            &archive_path,
+            tar_gz(&[("app/pom.xml", 0o644, Some(b"<project/>"))]),
+        )
+        .unwrap();
+
+        let project_dir = dir.path().join("APP");
+        let project_root = extract_batch_project(&archive_path, &project_dir).unwrap();
+
+        assert_eq!(project_root, project_dir);
+        assert_eq!(
+            fs::read(project_dir.join("pom.xml")).unwrap(),
+            b"<project/>"
+        );
+        assert!(!archive_path.exists());
+        assert!(!project_dir.join("APP.TGZ").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn zip_entries_keep_their_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("project.zip");
+        let mut writer = ZipWriter::new(File::create(&archive_path).unwrap());
+        for (name, mode) in [("mvnw", 0o755), ("pom.xml", 0o644)] {
+            let options = SimpleFileOptions::default().unix_permissions(mode);
+            writer.start_file(name, options).unwrap();
+            writer.write_all(b"#!/bin/sh\n").unwrap();
+        }
+        writer.finish().unwrap();
+        let archive = fs::read(&archive_path).unwrap();
+
+        // Without --extract-only the zip crate extracts the whole archive,
+        // with it the entries are extracted one by one
+        for extract_only in [vec![], vec![Pattern::new("mvnw").unwrap()]] {
+            let dest_path = tempfile::tempdir().unwrap();
+            extract_project(
+                ArchiveSource::Memory(&archive),
+                ArchiveFormat::Zip,
+                &extract_only,
+                dest_path.path(),
+            )
+            .unwrap();
+
+            let mode = |name| {
+                let metadata = fs::metadata(dest_path.path().join(name)).unwrap();
+                metadata.permissions().mode() & 0o777
+            };
+            assert_eq!(mode("mvnw"), 0o755);
+            assert_eq!(mode("pom.xml"), 0o644);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn gunzipped_scripts_are_executable() {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"#!/bin/sh\necho built\n").unwrap();
+        let archive = encoder.finish().unwrap();
+
+        let path = gunzip_single_file(
+            ArchiveSource::Memory(&archive),
+            Some("build.sh.gz"),
+            dir.path(),
+        )
+        .unwrap();
+
+        assert_eq!(path, dir.path().join("build.sh"));
+        assert_eq!(fs::read(&path).unwrap(), b"#!/bin/sh\necho built\n");
+        assert_ne!(fs::metadata(&path).unwrap().permissions().mode() & 0o111, 0);
+    }
+
+    /// A tar of `pom.xml`, `lib/app.jar` and the hardlink `run.jar` to it.
+    fn tar_with_hardlink() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(10);
+        builder
+            .append_data(&mut header, "pom.xml", &b"<project/>"[..])
+            .unwrap();
+        header.set_size(3);
+        builder
+            .append_data(&mut header, "lib/app.jar", &b"jar"[..])
+            .unwrap();
+        let mut link = tar::Header::new_gnu();
+        link.set_entry_type(EntryType::Link);
+        link.set_size(0);
+        builder
+            .append_link(&mut link, "run.jar", "lib/app.jar")
+            .unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn tar_hardlinks_are_unpacked() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = tar_with_hardlink();
+
+        extract_project(
+            ArchiveSource::Memory(&archive),
+            ArchiveFormat::Tar,
+            &[],
+            dir.path(),
+        )
+        .unwrap();
+
+        let target = fs::read(dir.path().join("lib/app.jar")).unwrap();
+        assert_eq!(target, b"jar");
+        assert_eq!(fs::read(dir.path().join("run.jar")).unwrap(), target);
+    }
+
+    #[test]
+    fn tar_hardlinks_to_filtered_entries_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = tar_with_hardlink();
+
+        extract_project(
+            ArchiveSource::Memory(&archive),
+            ArchiveFormat::Tar,
+            &[Pattern::new("run.jar").unwrap()],
+            dir.path(),
+        )
+        .unwrap();
+
+        assert!(dir.path().join("pom.xml").exists());
+        assert!(!dir.path().join("lib").exists());
+        assert!(!dir.path().join("run.jar").exists());
+    }
+
+    /// A tar of `pom.xml`, the symlink `lib` to `outside` and the hardlink
+    /// `link` to `target`.
+    fn tar_with_symlink_and_hardlink(outside: &Path, link: &str, target: &str) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(10);
+        builder
+            .append_data(&mut header, "pom.xml", &b"<project/>"[..])
+            .unwrap();
+        let mut symlink = tar::Header::new_gnu();
+        symlink.set_entry_type(EntryType::Symlink);
+        symlink.set_size(0);
+        builder.append_link(&mut symlink, "lib", outside).unwrap();
+        let mut hardlink = tar::Header::new_gnu();
+        hardlink.set_entry_type(EntryType::Link);
+        hardlink.set_size(0);
+        builder.append_link(&mut hardlink, link, target).unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn tar_hardlinks_through_symlinks_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let archive = tar_with_symlink_and_hardlink(outside.path(), "lib/pwned", "pom.xml");
+
+        let result = extract_project(
+            ArchiveSource::Memory(&archive),
+            ArchiveFormat::Tar,
+            &[],
+            dir.path(),
+        );
+
+        assert!(result.is_err());
+        assert_eq!(fs::read_dir(outside.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn tar_hardlinks_to_files_through_symlinks_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        fs::write(outside.path().join("passwd"), "secret").unwrap();
+        let archive = tar_with_symlink_and_hardlink(outside.path(), "run.jar", "lib/passwd");
+
+        let result = extract_project(
+            ArchiveSource::Memory(&archive),
+            ArchiveFormat::Tar,
+            &[],
+            dir.path(),
+        );
+
+        assert!(result.is_err());
+        let copied = WalkDir::new(dir.path())
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .any(|entry| entry.file_name() == "run.jar");
+        assert!(!copied);
+        assert_eq!(fs::read_dir(outside.path()).unwrap().count(), 1);
+    }
+
+    /// An uncompressed tar of a single `pom.xml`, with a header of the given
+    /// flavour.
+    fn tar(mut header: tar::Header) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        header.set_mode(0o644);
+        header.set_size(10);
+        builder
+            .append_data(&mut header, "pom.xml", &b"<project/>"[..])
+            .unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    fn zip(entries: &[&str]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for name in entries {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn sniff_format_recognizes_archives() {
+        let tar = tar(tar::Header::new_ustar());
+        let compress = |mut encoder: Box<dyn Write>| encoder.write_all(&tar).unwrap();
+
+        let mut gzip = Vec::new();
+        compress(Box::new(flate2::write::GzEncoder::new(
+            &mut gzip,
+            flate2::Compression::default(),
+        )));
+        let mut xz = Vec::new();
+        compress(Box::new(xz2::write::XzEncoder::new(&mut xz, 6)));
+        let mut bzip2 = Vec::new();
+        compress(Box::new(bzip2::write::BzEncoder::new(
+            &mut bzip2,
+            bzip2::Compression::default(),
+        )));
+
+        assert_eq!(sniff_format(&gzip), Some(ArchiveFormat::TarGz));
+        assert_eq!(sniff_format(&xz), Some(ArchiveFormat::TarXz));
+        assert_eq!(sniff_format(&bzip2), Some(ArchiveFormat::TarBz2));
+        assert_eq!(sniff_format(&zip(&["pom.xml"])), Some(ArchiveFormat::Zip));
+        assert_eq!(sniff_format(&zip(&[])), Some(ArchiveFormat::Zip));
+        assert_eq!(
+            sniff_format(&zip(&["META-INF/MANIFEST.MF", "App.class"])),
+            Some(ArchiveFormat::Zip)
+        );
+    }
+
+    #[test]
+    fn sniff_format_recognizes_plain_tar() {
+        // ustar and GNU headers have a magic, old ones only a valid checksum
+        for header in [
+            tar::Header::new_ustar(),
+            tar::Header::new_gnu(),
+            tar::Header::new_old(),
+        ] {
+            let tar = tar(header);
+            assert_eq!(sniff_format(&tar[..512]), Some(ArchiveFormat::Tar));
+        }
+
+        let mut corrupt = tar(tar::Header::new_old());
+        corrupt[0] ^= 1;
+        assert_eq!(sniff_format(&corrupt[..512]), None);
+    }
+
+    #[test]
+    fn sniff_format_rejects_ambiguous_and_short_input() {
+        let tar = tar(tar::Header::new_ustar());
+        for header in [
+            &b""[..],
+            &[0x1f],
+            b"PK",
+            b"PK\x03",
+            b"PKG-INFO: not a zip archive",
+            &[0xfd, b'7', b'z', b'X', b'Z'],
+            b"<project/>",
+            &[0; 512],
+            &tar[..511],
+        ] {
+            assert_eq!(sniff_format(header), None, "{:?}", header);
+        }
+    }
+}
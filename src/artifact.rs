@@ -0,0 +1,955 @@
+use crate::incremental::sha256_file;
+use crate::{BuildOptions, BuildSystem, CandidateArtifact, FailedArtifact};
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use dialoguer::Select;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use glob::{MatchOptions, Pattern};
+use log::{info, warn};
+use std::cmp::Ordering;
+use std::fs::{self, File};
+use std::io::{IsTerminal, Read, copy};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+use zip::result::ZipError;
+use zip::write::SimpleFileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+/// Kinds of artifacts monteur can deliver.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ArtifactKind {
+    /// A JAR from the build's artifact directory
+    #[default]
+    Jar,
+    /// The `.tar`/`.zip` bundles of Gradle's `application` plugin
+    Distribution,
+}
+
+/// Which files the artifact is selected from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ArtifactType {
+    /// JAR files
+    #[default]
+    Jar,
+    /// WAR files
+    War,
+    /// JAR and WAR files, preferring the type that comes first in
+    /// `--artifact-type-priority` among those built
+    Auto,
+}
+
+/// File types of deliverable artifacts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ArtifactFileType {
+    Jar,
+    War,
+}
+
+impl ArtifactFileType {
+    fn extension(self) -> &'static str {
+        match self {
+            ArtifactFileType::Jar => "jar",
+            ArtifactFileType::War => "war",
+        }
+    }
+}
+
+/// Returns the file extensions of the artifacts to deliver, for
+/// `--artifact-type`; with `auto`, in the order of `--artifact-type-priority`.
+pub(crate) fn artifact_extensions(options: &BuildOptions) -> Vec<&'static str> {
+    match options.artifact_type {
+        ArtifactType::Jar => vec!["jar"],
+        ArtifactType::War => vec!["war"],
+        ArtifactType::Auto => {
+            let mut extensions = Vec::new();
+            for file_type in &options.artifact_type_priority {
+                if !extensions.contains(&file_type.extension()) {
+                    extensions.push(file_type.extension());
+                }
+            }
+            extensions
+        }
+    }
+}
+
+/// Names the file types of `extensions` for messages, e.g. "WAR/JAR".
+fn file_types(extensions: &[&str]) -> String {
+    extensions
+        .iter()
+        .map(|extension| extension.to_ascii_uppercase())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Whether `path` has one of the `extensions`.
+pub(crate) fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .is_some_and(|ext| extensions.iter().any(|extension| ext == *extension))
+}
+
+/// Containers the selected artifact can be delivered in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// The JAR itself
+    #[default]
+    Jar,
+    /// A zip archive holding the JAR and its companion files
+    Zip,
+    /// A gzipped tarball holding the JAR and its companion files
+    #[value(name = "tar.gz")]
+    TarGz,
+}
+
+/// An artifact copied to the output directory.
+pub(crate) struct CopiedArtifact {
+    /// Where the build produced it
+    pub(crate) source: PathBuf,
+    /// Where it was copied to, or with `--output-format zip` or `tar.gz` its
+    /// path in the output directory as if it was, see [`package_artifacts`]
+    pub(crate) path: PathBuf,
+}
+
+/// Finds the JARs, or the other archives with one of the `extensions`, in the
+/// artifact directories of the project. Directories that don't exist are
+/// skipped, as long as one of them does.
+pub(crate) fn find_jars(
+    project_root: &Path,
+    artifact_paths: &[&str],
+    build_system: BuildSystem,
+    extensions: &[&str],
+) -> Result<Vec<PathBuf>> {
+    // collect the .jar files in the artifact_path folders
+    info!(
+        "Searching for {} files in {}",
+        file_types(extensions),
+        artifact_paths.join(", ")
+    );
+
+    // Pants writes the package of each target to a subdirectory of dist/
+    let search_depth = if build_system == BuildSystem::Pants {
+        usize::MAX
+    } else {
+        1
+    };
+    let mut jar_files = Vec::new();
+    let mut searched_any = false;
+    for artifact_path in artifact_paths {
+        let search_dir = project_root.join(artifact_path);
+        // Fail on a missing directory instead of finding no JARs in it
+        if let Err(err) = fs::read_dir(&search_dir) {
+            if let [_] = artifact_paths {
+                return Err(err).context(format!("Failed to read directory: {}", artifact_path));
+            }
+            info!("Skipping {}: {}", artifact_path, err);
+            continue;
+        }
+        searched_any = true;
+
+        for entry in WalkDir::new(&search_dir)
+            .min_depth(1)
+            .max_depth(search_depth)
+            .sort_by_file_name()
+        {
+            let Ok(entry) = entry else {
+                continue;
+            };
+            let path = entry.into_path();
+            if path.is_file() && has_extension(&path, extensions) && !jar_files.contains(&path) {
+                jar_files.push(path);
+            }
+        }
+    }
+    if !searched_any {
+        anyhow::bail!(
+            "None of the artifact directories could be read: {}",
+            artifact_paths.join(", ")
+        );
+    }
+    Ok(jar_files)
+}
+
+/// Finds the JAR files whose path relative to the project root matches
+/// `glob`, for `--artifact-glob`, among the archives with one of the
+/// `extensions`. `*` doesn't match across directories. Like with
+/// `--all-artifacts`, hidden directories and anything deeper than
+/// `max_depth` are skipped.
+pub(crate) fn find_jars_matching(
+    project_root: &Path,
+    glob: &Pattern,
+    extensions: &[&str],
+    max_depth: usize,
+) -> Vec<PathBuf> {
+    info!(
+        "Searching for {} files matching {}",
+        file_types(extensions),
+        glob
+    );
+    let match_options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    WalkDir::new(project_root)
+        .min_depth(1)
+        .max_depth(max_depth)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| !entry.file_name().as_encoded_bytes().starts_with(b"."))
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.into_path())
+        .filter(|path| path.is_file() && has_extension(path, extensions))
+        .filter(|path| {
+            path.strip_prefix(project_root)
+                .is_ok_and(|relative| glob.matches_path_with(relative, match_options))
+        })
+        .collect()
+}
+
+/// Selects the JAR to deliver from the `jar_files` found in `artifact_path`
+/// and copies it to the output directory, named `output_name` if given,
+/// returning the path of the copy. Nothing is copied if it gets packaged
+/// with `--output-format`.
+pub(crate) fn copy_selected_artifact(
+    jar_files: Vec<PathBuf>,
+    artifact_path: &str,
+    build_system: BuildSystem,
+    output_name: Option<&str>,
+    options: &BuildOptions,
+) -> Result<CopiedArtifact> {
+    let output_dir = options.output_dir.as_path();
+
+    if jar_files.is_empty() {
+        anyhow::bail!(
+            "No {} files found in {}",
+            file_types(&artifact_extensions(options)),
+            artifact_path
+        );
+    }
+    let candidates = select_artifact(jar_files, build_system, options)?;
+    let Some(jar_file) = candidates
+        .iter()
+        .find(|candidate| candidate.selected)
+        .map(|candidate| &candidate.path)
+    else {
+        let excluded: Vec<_> = candidates
+            .iter()
+            .map(|candidate| {
+                format!(
+                    "  {}: {}",
+                    candidate.path.display(),
+                    candidate.excluded_reason.as_deref().unwrap_or_default()
+                )
+            })
+            .collect();
+        anyhow::bail!(
+            "No suitable artifact found in {}. Candidates:\n{}",
+            artifact_path,
+            excluded.join("\n")
+        );
+    };
+
+    info!("Found JAR file: {}", jar_file.display());
+
+    // Create output directory if it doesn't exist
+    if !output_dir.exists() {
+        fs::create_dir_all(output_dir).context("Failed to create output directory")?;
+        info!("Created output directory at: {}", output_dir.display());
+    }
+
+    // Copy the JAR file to the output directory
+    let output_path = match output_name {
+        Some(name) => output_dir.join(name),
+        None => output_dir.join(jar_file.file_name().unwrap()),
+    };
+
+    validate_jar(jar_file).context("Selected artifact is not a valid JAR")?;
+    if options.output_format == OutputFormat::Jar {
+        copy_artifact(jar_file, &output_path, !options.no_overwrite)?;
+        info!("Copied JAR file to: {}", output_path.display());
+    }
+    Ok(CopiedArtifact {
+        source: jar_file.clone(),
+        path: output_path,
+    })
+}
+
+/// Selects the JAR to deliver from `jar_files`, returning every one of them
+/// with the reason it was excluded, if it was, and whether it was selected.
+/// None is selected if all of them were excluded.
+pub(crate) fn select_artifact(
+    jar_files: Vec<PathBuf>,
+    build_system: BuildSystem,
+    options: &BuildOptions,
+) -> Result<Vec<CandidateArtifact>> {
+    let mut candidates: Vec<_> = jar_files
+        .into_iter()
+        .map(|path| {
+            let size = fs::metadata(&path).map(|metadata| metadata.len()).ok();
+            // Source and Javadoc JARs are never the artifact to deliver, and
+            // stub JARs are tiny compared to the deliverable
+            let excluded_reason = match (exclusion_reason(&path), options.min_artifact_size, size) {
+                (Some(reason), _, _) => Some(reason.to_string()),
+                (None, Some(min_size), Some(size)) if size < min_size => Some(format!(
+                    "{} bytes, below --min-artifact-size {}",
+                    size, min_size
+                )),
+                _ => None,
+            };
+            if let Some(reason) = &excluded_reason {
+                info!("Ignoring {}: {}", path.display(), reason);
+            }
+            CandidateArtifact {
+                path,
+                size: size.unwrap_or_default(),
+                selected: false,
+                excluded_reason,
+            }
+        })
+        .collect();
+
+    // The heuristics below choose among the files of a single type
+    if options.artifact_type == ArtifactType::Auto {
+        prefer_artifact_type(&mut candidates, &artifact_extensions(options));
+    }
+
+    let jar_files: Vec<_> = candidates
+        .iter()
+        .filter(|candidate| candidate.excluded_reason.is_none())
+        .map(|candidate| candidate.path.clone())
+        .collect();
+    if jar_files.is_empty() {
+        return Ok(candidates);
+    }
+
+    let jar_file = if build_system == BuildSystem::Maven && options.no_shaded {
+        select_plain_maven_jar(&jar_files, &options.exclude_prefixes)
+    } else if build_system == BuildSystem::Maven {
+        select_maven_jar(&jar_files, &options.exclude_prefixes)
+    } else {
+        if options.no_shaded {
+            warn!("--no-shaded only applies to Maven builds, ignoring it");
+        }
+        // For Gradle, use the original longest filename logic
+        longest_name(&jar_files)
+    };
+
+    // Let the user confirm or override the heuristic pick, but only if there
+    // is someone to ask and no --artifact-glob already narrowed it down
+    let jar_file = if options.interactive
+        && options.artifact_glob.is_none()
+        && jar_files.len() > 1
+        && std::io::stdin().is_terminal()
+    {
+        prompt_for_artifact(&jar_files, jar_file)?
+    } else {
+        jar_file
+    };
+
+    for candidate in &mut candidates {
+        candidate.selected = candidate.path == *jar_file;
+    }
+    Ok(candidates)
+}
+
+/// For `--artifact-type auto`, rules out the candidates of all but the first
+/// type in `extensions`, the order of `--artifact-type-priority`, that was
+/// built, and logs why that type was chosen.
+fn prefer_artifact_type(candidates: &mut [CandidateArtifact], extensions: &[&str]) {
+    let built = |extension: &str| {
+        candidates.iter().any(|candidate| {
+            candidate.excluded_reason.is_none() && has_extension(&candidate.path, &[extension])
+        })
+    };
+    let Some(position) = extensions.iter().position(|extension| built(extension)) else {
+        return;
+    };
+    let preferred = extensions[position];
+    let priority = extensions.join(",");
+    if position == 0 {
+        info!(
+            "Selecting a {} file: {} comes first in --artifact-type-priority {}",
+            file_types(&[preferred]),
+            file_types(&[preferred]),
+            priority
+        );
+    } else {
+        info!(
+            "Selecting a {} file: no {} file was built, and {} comes next in --artifact-type-priority {}",
+            file_types(&[preferred]),
+            file_types(&extensions[..position]),
+            file_types(&[preferred]),
+            priority
+        );
+    }
+
+    for candidate in candidates {
+        if candidate.excluded_reason.is_none() && !has_extension(&candidate.path, &[preferred]) {
+            let reason = format!(
+                "{} preferred by --artifact-type-priority",
+                file_types(&[preferred])
+            );
+            info!("Passing over {}: {}", candidate.path.display(), reason);
+            candidate.excluded_reason = Some(reason);
+        }
+    }
+}
+
+/// Selects the JAR of a Maven build, following the priority list:
+/// 1. a JAR ending with -shaded.jar
+/// 2. a JAR not starting with one of `excluded_prefixes`, by default the
+///    original- input of the Shade plugin
+/// 3. the JAR with the longest name
+fn select_maven_jar<'a>(jar_files: &'a [PathBuf], excluded_prefixes: &[String]) -> &'a PathBuf {
+    if let Some(jar) = jar_files
+        .iter()
+        .find(|path| file_name_bytes(path).ends_with(b"-shaded.jar"))
+    {
+        info!("Found shaded JAR: {}", jar.display());
+        return jar;
+    }
+
+    let others: Vec<_> = jar_files
+        .iter()
+        .filter(|path| {
+            let name = file_name_bytes(path);
+            match excluded_prefixes
+                .iter()
+                .find(|prefix| name.starts_with(prefix.as_bytes()))
+            {
+                Some(prefix) => {
+                    info!(
+                        "Passing over {}: intermediate JAR ({} prefix)",
+                        path.display(),
+                        prefix
+                    );
+                    false
+                }
+                None => true,
+            }
+        })
+        .collect();
+    if let Some(jar) = others.first() {
+        info!("Found default JAR: {}", jar.display());
+        return jar;
+    }
+
+    info!("Only intermediate JARs found, using the one with the longest filename");
+    longest_name(jar_files)
+}
+
+/// Selects the plain JAR of a Maven build for `--no-shaded`, following the
+/// priority list:
+/// 1. the JAR the Shade plugin leaves when it replaces the main JAR, named
+///    with one of `excluded_prefixes` (`original-` by default)
+/// 2. the JAR with the shortest name, as classifiers such as -shaded or
+///    -jar-with-dependencies are appended to the plain name
+fn select_plain_maven_jar<'a>(
+    jar_files: &'a [PathBuf],
+    excluded_prefixes: &[String],
+) -> &'a PathBuf {
+    let candidates: Vec<_> = jar_files
+        .iter()
+        .filter(|path| {
+            let shaded = file_name_bytes(path).ends_with(b"-shaded.jar");
+            if shaded {
+                info!("Passing over {}: shaded JAR (--no-shaded)", path.display());
+            }
+            !shaded
+        })
+        .collect();
+    if let Some(jar) = candidates.iter().find(|path| {
+        let name = file_name_bytes(path);
+        excluded_prefixes
+            .iter()
+            .any(|prefix| name.starts_with(prefix.as_bytes()))
+    }) {
+        info!("Found JAR replaced by the Shade plugin: {}", jar.display());
+        return jar;
+    }
+
+    let jar = candidates
+        .into_iter()
+        .min_by(|a, b| {
+            file_name_bytes(a)
+                .len()
+                .cmp(&file_name_bytes(b).len())
+                .then_with(|| by_name(a, b))
+        })
+        .unwrap_or_else(|| longest_name(jar_files));
+    info!("Found plain JAR: {}", jar.display());
+    jar
+}
+
+/// Returns the JAR with the longest file name; `jar_files` must not be empty.
+fn longest_name(jar_files: &[PathBuf]) -> &PathBuf {
+    jar_files
+        .iter()
+        .min_by(|a, b| {
+            file_name_bytes(b)
+                .len()
+                .cmp(&file_name_bytes(a).len())
+                .then_with(|| by_name(a, b))
+        })
+        .expect("no JAR files to choose from")
+}
+
+/// Breaks ties between JARs whose names are equally long: the file name that
+/// sorts first wins, then the path, so that the same build always yields the
+/// same artifact, whatever order the JARs were found in.
+fn by_name(a: &Path, b: &Path) -> Ordering {
+    file_name_bytes(a)
+        .cmp(file_name_bytes(b))
+        .then_with(|| a.cmp(b))
+}
+
+/// Returns why a JAR is never delivered as the selected artifact, if it isn't.
+/// WARs are held to the same suffixes.
+fn exclusion_reason(path: &Path) -> Option<&'static str> {
+    let stem = path.file_stem().unwrap_or_default().as_encoded_bytes();
+    if stem.ends_with(b"-sources") {
+        Some("source JAR (-sources suffix)")
+    } else if stem.ends_with(b"-javadoc") {
+        Some("Javadoc JAR (-javadoc suffix)")
+    } else {
+        None
+    }
+}
+
+/// Asks the user to pick one of the candidate JARs, preselecting `default`.
+fn prompt_for_artifact<'a>(candidates: &'a [PathBuf], default: &'a PathBuf) -> Result<&'a PathBuf> {
+    let items: Vec<_> = candidates
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    let default_index = candidates
+        .iter()
+        .position(|path| path == default)
+        .unwrap_or(0);
+
+    let selection = Select::new()
+        .with_prompt("Multiple JAR files found, select the artifact to copy")
+        .items(&items)
+        .default(default_index)
+        .interact()
+        .context("Failed to prompt for the artifact")?;
+    Ok(&candidates[selection])
+}
+
+/// Copies every JAR (or WAR, with `--artifact-type`) found in the artifact
+/// directory of the root project and of all its modules (e.g. `core/target/`)
+/// to the output directory. `--artifact-search-depth` bounds how deep below
+/// the project root JARs are searched for.
+///
+/// A JAR that is corrupt or can't be copied doesn't stop the others; it is
+/// reported as failed, and only fails the whole copy if nothing could be
+/// copied or `--require-all` is set.
+pub(crate) fn copy_all_artifacts(
+    project_root: &Path,
+    artifact_path: &str,
+    options: &BuildOptions,
+) -> Result<(Vec<CopiedArtifact>, Vec<FailedArtifact>)> {
+    let extensions = &artifact_extensions(options);
+    let output_dir = options.output_dir.as_path();
+    if artifact_path.is_empty() {
+        info!(
+            "Searching for {} files in {}",
+            file_types(extensions),
+            project_root.display()
+        );
+    } else {
+        info!(
+            "Searching for {} files in all {} directories",
+            file_types(extensions),
+            artifact_path
+        );
+    }
+
+    let artifact_dir = Path::new(artifact_path);
+    let mut copied = Vec::new();
+    let mut failed = Vec::new();
+
+    let walker = WalkDir::new(project_root).max_depth(options.artifact_search_depth);
+    for entry in walker.into_iter().filter_entry(|entry| {
+        // Skip hidden directories such as .git, .mvn or .gradle
+        entry.depth() == 0 || !entry.file_name().as_encoded_bytes().starts_with(b".")
+    }) {
+        let entry = entry.context("Failed to walk project directory")?;
+        let path = entry.path();
+        if !entry.file_type().is_file() || !has_extension(path, extensions) {
+            continue;
+        }
+
+        // Only pick up JARs that live directly inside a module's artifact directory
+        let Some(parent) = path
+            .parent()
+            .and_then(|parent| parent.strip_prefix(project_root).ok())
+        else {
+            continue;
+        };
+        let Some(module_dir) = strip_suffix_path(parent, artifact_dir) else {
+            continue;
+        };
+
+        let target_dir = if options.preserve_module_paths {
+            output_dir.join(module_dir)
+        } else {
+            output_dir.to_path_buf()
+        };
+        fs::create_dir_all(&target_dir)
+            .context(format!("Failed to create directory {:?}", target_dir))?;
+
+        let output_path = target_dir.join(path.file_name().unwrap());
+        let result = validate_jar(path)
+            .context(format!("{:?} is not a valid JAR", path))
+            .and_then(|()| copy_artifact(path, &output_path, !options.no_overwrite));
+        match result {
+            Ok(()) => {
+                info!("Copied JAR file to: {}", output_path.display());
+                copied.push(CopiedArtifact {
+                    source: path.to_path_buf(),
+                    path: output_path,
+                });
+            }
+            Err(err) if options.require_all => return Err(err),
+            Err(err) => {
+                warn!("Skipping {}: {:#}", path.display(), err);
+                failed.push(FailedArtifact {
+                    path: path.to_path_buf(),
+                    error: format!("{:#}", err),
+                });
+            }
+        }
+    }
+
+    if copied.is_empty() {
+        match failed.as_slice() {
+            [] => anyhow::bail!(
+                "No {} files found in any {} directory",
+                file_types(extensions),
+                artifact_path
+            ),
+            _ => anyhow::bail!(
+                "None of the {} JAR files could be copied: {}",
+                failed.len(),
+                failed
+                    .iter()
+                    .map(|failure| failure.error.as_str())
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            ),
+        }
+    }
+
+    info!("Copied {} JAR files", copied.len());
+    if !failed.is_empty() {
+        warn!(
+            "{} JAR files could not be copied: {}",
+            failed.len(),
+            failed
+                .iter()
+                .map(|failure| failure.path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    Ok((copied, failed))
+}
+
+/// Copies every distribution archive (`.tar` or `.zip`) found in
+/// `distribution_path` to the output directory.
+pub(crate) fn copy_distributions(
+    project_root: &Path,
+    distribution_path: &str,
+    output_dir: &Path,
+    overwrite: bool,
+) -> Result<Vec<CopiedArtifact>> {
+    info!("Searching for distributions in {}", distribution_path);
+
+    let mut distributions = fs::read_dir(project_root.join(distribution_path))
+        .context(format!("Failed to read directory: {}", distribution_path))?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let is_archive = path
+                .extension()
+                .is_some_and(|ext| ext == "tar" || ext == "zip");
+            (path.is_file() && is_archive).then_some(path)
+        })
+        .collect::<Vec<_>>();
+    distributions.sort();
+
+    if distributions.is_empty() {
+        anyhow::bail!("No distribution archives found in {}", distribution_path);
+    }
+
+    fs::create_dir_all(output_dir).context("Failed to create output directory")?;
+
+    let mut copied = Vec::new();
+    for path in distributions {
+        let output_path = output_dir.join(path.file_name().unwrap());
+        copy_artifact(&path, &output_path, overwrite)?;
+        info!("Copied distribution to: {}", output_path.display());
+        copied.push(CopiedArtifact {
+            source: path,
+            path: output_path,
+        });
+    }
+    Ok(copied)
+}
+
+/// Copies a single file that needed no build, such as a downloaded JAR, to
+/// the output directory; JARs are validated first.
+pub(crate) fn copy_single_file(
+    path: &Path,
+    output_dir: &Path,
+    overwrite: bool,
+) -> Result<CopiedArtifact> {
+    if path.extension().is_some_and(|ext| ext == "jar") {
+        validate_jar(path).context("Downloaded artifact is not a valid JAR")?;
+    }
+    fs::create_dir_all(output_dir).context("Failed to create output directory")?;
+    let output_path = output_dir.join(path.file_name().unwrap());
+    copy_artifact(path, &output_path, overwrite)?;
+    info!("Copied file to: {}", output_path.display());
+    Ok(CopiedArtifact {
+        source: path.to_path_buf(),
+        path: output_path,
+    })
+}
+
+/// Checks that a JAR is a readable zip with a valid central directory, to
+/// catch files truncated by e.g. a full disk before they are delivered, and
+/// logs the main class named by its manifest.
+fn validate_jar(path: &Path) -> Result<()> {
+    let file = File::open(path).context(format!("Failed to open {:?}", path))?;
+    let mut archive =
+        ZipArchive::new(file).context(format!("Failed to read {:?} as a zip archive", path))?;
+    match main_class(&mut archive)? {
+        Some(main_class) => info!("Main class of {}: {}", path.display(), main_class),
+        None => info!("{} has no Main-Class in its manifest", path.display()),
+    }
+    Ok(())
+}
+
+/// Upper bound for the size of a `META-INF/MANIFEST.MF`; real manifests are a
+/// few KiB, so anything larger is treated as a crafted JAR.
+const MAX_MANIFEST_SIZE: u64 = 1024 * 1024;
+
+/// Reads the `Main-Class` attribute from the manifest of a JAR, reading at
+/// most [`MAX_MANIFEST_SIZE`] bytes of it.
+fn main_class(archive: &mut ZipArchive<File>) -> Result<Option<String>> {
+    let manifest = match archive.by_name("META-INF/MANIFEST.MF") {
+        Ok(manifest) => manifest,
+        Err(ZipError::FileNotFound) => return Ok(None),
+        Err(err) => return Err(err).context("Failed to read META-INF/MANIFEST.MF"),
+    };
+    // The declared size can't be trusted, so the read itself is bounded too
+    let declared_size = manifest.size();
+    let mut content = Vec::new();
+    manifest
+        .take(MAX_MANIFEST_SIZE + 1)
+        .read_to_end(&mut content)
+        .context("Failed to read META-INF/MANIFEST.MF")?;
+    if declared_size > MAX_MANIFEST_SIZE || content.len() as u64 > MAX_MANIFEST_SIZE {
+        anyhow::bail!(
+            "META-INF/MANIFEST.MF is implausibly large ({} bytes, at most {} are accepted)",
+            declared_size.max(content.len() as u64),
+            MAX_MANIFEST_SIZE
+        );
+    }
+
+    // Continuation lines start with a single space
+    let content = String::from_utf8_lossy(&content);
+    let mut main_class: Option<String> = None;
+    for line in content.lines() {
+        if let Some(continuation) = line.strip_prefix(' ') {
+            if let Some(main_class) = &mut main_class {
+                main_class.push_str(continuation);
+            }
+            continue;
+        }
+        if main_class.is_some() {
+            break;
+        }
+        main_class = line
+            .split_once(':')
+            .filter(|(name, _)| name.eq_ignore_ascii_case("Main-Class"))
+            .map(|(_, value)| value.trim_start().to_string());
+    }
+    Ok(main_class.filter(|main_class| !main_class.is_empty()))
+}
+
+/// Copies the POM of each Maven JAR next to it in the output directory, named
+/// after the JAR (`app-1.0.jar` gets `app-1.0.pom`). A `.pom` generated next to
+/// the JAR in `target/` is preferred over the module's `pom.xml`.
+pub(crate) fn copy_poms(artifacts: &[CopiedArtifact], overwrite: bool) -> Result<Vec<PathBuf>> {
+    let mut copied = Vec::new();
+    for artifact in artifacts {
+        let Some(pom) = find_pom(&artifact.source) else {
+            continue;
+        };
+        let output_path = artifact.path.with_extension("pom");
+        copy_artifact(&pom, &output_path, overwrite)?;
+        info!("Copied POM to: {}", output_path.display());
+        copied.push(output_path);
+    }
+    Ok(copied)
+}
+
+/// Finds the POM of the Maven JAR at `jar_file`: the `.pom` generated next to
+/// it, or else the `pom.xml` of its module.
+fn find_pom(jar_file: &Path) -> Option<PathBuf> {
+    let generated = jar_file.with_extension("pom");
+    if generated.is_file() {
+        return Some(generated);
+    }
+    match jar_file
+        .parent()
+        .and_then(Path::parent)
+        .map(|module_dir| module_dir.join("pom.xml"))
+    {
+        Some(pom) if pom.is_file() => Some(pom),
+        _ => {
+            warn!("No POM found for {}", jar_file.display());
+            None
+        }
+    }
+}
+
+/// Copies an artifact to the output directory, refusing to replace an
+/// existing file unless `overwrite` is set. The permission bits are copied
+/// along, so executable artifacts such as start scripts stay executable.
+fn copy_artifact(source: &Path, target: &Path, overwrite: bool) -> Result<()> {
+    if !overwrite && target.exists() {
+        anyhow::bail!("{:?} already exists and --no-overwrite was given", target);
+    }
+    fs::copy(source, target).context(format!("Failed to copy {:?} to {:?}", source, target))?;
+    Ok(())
+}
+
+/// Returns the file name of a path as raw bytes, so that comparisons also
+/// match names that aren't valid UTF-8 instead of their lossy conversion.
+fn file_name_bytes(path: &Path) -> &[u8] {
+    path.file_name().unwrap_or_default().as_encoded_bytes()
+}
+
+/// Returns `path` without the trailing `suffix` components, if it ends with them.
+fn strip_suffix_path<'a>(path: &'a Path, suffix: &Path) -> Option<&'a Path> {
+    if !path.ends_with(suffix) {
+        return None;
+    }
+    let mut module_dir = path;
+    for _ in suffix.components() {
+        module_dir = module_dir.parent()?;
+    }
+    Some(module_dir)
+}
+
+/// Packages the `artifacts` together with their POMs, if `include_pom`, into
+/// a single archive in the output directory, named after the first of them,
+/// and writes its SHA-256 checksum next to it in `sha256sum` format. The
+/// files are read from where the build produced them and named as they would
+/// have been in the output directory, which gets no loose files. Returns the
+/// path of the archive.
+pub(crate) fn package_artifacts(
+    artifacts: &[CopiedArtifact],
+    include_pom: bool,
+    format: OutputFormat,
+    overwrite: bool,
+) -> Result<PathBuf> {
+    let first = artifacts.first().context("No artifact to package")?;
+    let stem = first
+        .path
+        .file_stem()
+        .context(format!("{:?} has no file name", first.path))?
+        .to_string_lossy();
+    let package_path = first.path.with_file_name(match format {
+        OutputFormat::Jar => return Ok(first.path.clone()),
+        OutputFormat::Zip => format!("{}.zip", stem),
+        OutputFormat::TarGz => format!("{}.tar.gz", stem),
+    });
+    if !overwrite && package_path.exists() {
+        anyhow::bail!(
+            "{:?} already exists and --no-overwrite was given",
+            package_path
+        );
+    }
+
+    // The source of each file and its name in the package
+    let mut files = Vec::new();
+    for artifact in artifacts {
+        files.push((artifact.source.clone(), artifact.path.clone()));
+        if include_pom {
+            files.extend(
+                find_pom(&artifact.source).map(|pom| (pom, artifact.path.with_extension("pom"))),
+            );
+        }
+    }
+
+    let file =
+        File::create(&package_path).context(format!("Failed to create {:?}", package_path))?;
+    match format {
+        OutputFormat::Jar => unreachable!(),
+        OutputFormat::Zip => {
+            let mut zip = ZipWriter::new(file);
+            for (source, path) in &files {
+                let name = path.file_name().unwrap().to_string_lossy();
+                zip.start_file(name, SimpleFileOptions::default())
+                    .context(format!("Failed to add {:?} to {:?}", source, package_path))?;
+                let mut reader =
+                    File::open(source).context(format!("Failed to open {:?}", source))?;
+                copy(&mut reader, &mut zip)
+                    .context(format!("Failed to add {:?} to {:?}", source, package_path))?;
+            }
+            zip.finish()
+                .context(format!("Failed to write {:?}", package_path))?;
+        }
+        OutputFormat::TarGz => {
+            let mut tar = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+            for (source, path) in &files {
+                tar.append_path_with_name(source, path.file_name().unwrap())
+                    .context(format!("Failed to add {:?} to {:?}", source, package_path))?;
+            }
+            tar.into_inner()
+                .and_then(GzEncoder::finish)
+                .context(format!("Failed to write {:?}", package_path))?;
+        }
+    }
+    info!("Packaged the artifacts as: {}", package_path.display());
+
+    let checksum_path = package_path.with_file_name(format!(
+        "{}.sha256",
+        package_path.file_name().unwrap().to_string_lossy()
+    ));
+    let checksum = format!(
+        "{}  {}\n",
+        sha256_file(&package_path)?,
+        package_path.file_name().unwrap().to_string_lossy()
+    );
+    fs::write(&checksum_path, checksum).context(format!("Failed to write {:?}", checksum_path))?;
+    info!("Wrote checksum to: {}", checksum_path.display());
+    Ok(package_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn copy_artifact_keeps_the_execute_bit() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("run.sh");
+        fs::write(&source, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&source, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let target = dir.path().join("out.sh");
+        copy_artifact(&source, &target, false).unwrap();
+
+        assert_eq!(
+            fs::metadata(&target).unwrap().permissions().mode() & 0o777,
+            0o755
+        );
+    }
+}
@@ -0,0 +1,952 @@
+use crate::BuildOptions;
+use crate::fs_util::make_executable;
+use crate::project;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use log::{debug, info, trace, warn};
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Files whose presence marks a Maven project (including Polyglot Maven).
+pub(crate) const MAVEN_BUILD_FILES: [&str; 8] = [
+    "pom.xml",
+    "pom.atom",
+    "pom.clj",
+    "pom.groovy",
+    "pom.rb",
+    "pom.scala",
+    "pom.yaml",
+    "pom.yml",
+];
+
+/// Gradle files needed to build a project, on top of the `gradle/` directory.
+pub(crate) const GRADLE_BUILD_FILES: [&str; 6] = [
+    "gradlew",
+    "build.gradle",
+    "build.gradle.kts",
+    "settings.gradle",
+    "settings.gradle.kts",
+    "gradle.properties",
+];
+
+/// Pants files needed to build a project; the `pants` launcher script or
+/// `pants.toml` mark a Pants project, the `BUILD` files declare its targets.
+pub(crate) const PANTS_BUILD_FILES: [&str; 4] = ["pants", "pants.toml", "BUILD", "BUILD.pants"];
+
+/// Files whose presence marks a Buck2 project.
+pub(crate) const BUCK2_BUILD_FILES: [&str; 2] = [".buckconfig", "BUCK"];
+
+/// Process ID of the build that is currently running.
+static BUILD_PROCESS: Mutex<Option<u32>> = Mutex::new(None);
+
+/// Build systems monteur knows how to drive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildSystem {
+    Maven,
+    Gradle,
+    Pants,
+    Buck2,
+    /// A user-supplied build command, see `--custom-build`
+    Custom,
+    /// Nothing was built, the download was the artifact itself
+    Prebuilt,
+}
+
+impl BuildSystem {
+    /// Directory, relative to a (module) project root, the build writes its JARs to.
+    /// Custom builds name theirs with `--custom-artifact-path`, so this is the
+    /// project root for them.
+    pub fn artifact_path(self) -> &'static str {
+        match self {
+            BuildSystem::Maven => "target/",
+            BuildSystem::Gradle => "build/libs/",
+            BuildSystem::Pants => "dist/",
+            BuildSystem::Buck2 => "buck-out/",
+            BuildSystem::Custom | BuildSystem::Prebuilt => "./",
+        }
+    }
+
+    /// Directory, relative to the project root, the build writes its
+    /// distribution bundles to, if it has any (see `--artifact-kind`).
+    pub fn distribution_path(self) -> Option<&'static str> {
+        match self {
+            BuildSystem::Gradle => Some("build/distributions/"),
+            BuildSystem::Maven
+            | BuildSystem::Pants
+            | BuildSystem::Buck2
+            | BuildSystem::Custom
+            | BuildSystem::Prebuilt => None,
+        }
+    }
+}
+
+impl fmt::Display for BuildSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BuildSystem::Maven => "maven",
+            BuildSystem::Gradle => "gradle",
+            BuildSystem::Pants => "pants",
+            BuildSystem::Buck2 => "buck2",
+            BuildSystem::Custom => "custom",
+            BuildSystem::Prebuilt => "prebuilt",
+        })
+    }
+}
+
+/// How the build treats the project's tests.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum SkipMode {
+    /// Neither compile nor run the tests (`-Dmaven.test.skip=true`)
+    #[default]
+    Skip,
+    /// Compile the tests but don't run them (`-DskipTests`)
+    SkipTests,
+    /// Compile and run the tests
+    Run,
+}
+
+/// Detects the build system based on the presence of build files.
+pub(crate) fn detect_build_system(project_root: &Path) -> Option<BuildSystem> {
+    trace!("Detecting the build system in {}", project_root.display());
+    let candidates: [(BuildSystem, &[&str]); 4] = [
+        (BuildSystem::Maven, &MAVEN_BUILD_FILES),
+        (BuildSystem::Gradle, &["gradlew"]),
+        (BuildSystem::Pants, &["pants", "pants.toml"]),
+        (BuildSystem::Buck2, &BUCK2_BUILD_FILES),
+    ];
+    for (build_system, files) in candidates {
+        if files
+            .iter()
+            .any(|file| probe(project_root, file) && is_plausible(project_root, file))
+        {
+            trace!("Detected {:?}", build_system);
+            return Some(build_system);
+        }
+    }
+    trace!("No build files found");
+    None
+}
+
+/// Checks whether `file` exists in `project_root`, tracing the result.
+fn probe(project_root: &Path, file: &str) -> bool {
+    let exists = project_root.join(file).exists();
+    trace!("  {}: {}", file, if exists { "found" } else { "not found" });
+    exists
+}
+
+/// Checks the contents of a build file found in `project_root`, where they
+/// tell a real project from e.g. a test fixture, tracing the result.
+fn is_plausible(project_root: &Path, file: &str) -> bool {
+    if file != "pom.xml" {
+        return true;
+    }
+    let plausible =
+        fs::read_to_string(project_root.join(file)).is_ok_and(|pom| project::is_maven_pom(&pom));
+    if !plausible {
+        trace!("  {}: ignored, the root element isn't <project>", file);
+    }
+    plausible
+}
+
+/// Runs the build of the project at `project_root`. Returns the outputs the
+/// build tool reported, for tools like Buck2 that don't write them to a fixed
+/// directory.
+pub(crate) fn run_build(
+    build_system: BuildSystem,
+    project_root: &Path,
+    options: &BuildOptions,
+) -> Result<Option<Vec<PathBuf>>> {
+    if options.gradle_cache.is_some() && build_system != BuildSystem::Gradle {
+        warn!("--gradle-cache only applies to Gradle builds, ignoring it");
+    }
+    if !options.maven_defines.is_empty() && build_system != BuildSystem::Maven {
+        warn!("--maven-define only applies to Maven builds, ignoring it");
+    }
+    if options.maven_local_repo.is_some() && build_system != BuildSystem::Maven {
+        warn!("--maven-local-repo only applies to Maven builds, ignoring it");
+    }
+    if options.use_bundled_maven_config && build_system != BuildSystem::Maven {
+        warn!("--use-bundled-maven-config only applies to Maven builds, ignoring it");
+    }
+    if !options.gradle_properties.is_empty() && build_system != BuildSystem::Gradle {
+        warn!("--gradle-property only applies to Gradle builds, ignoring it");
+    }
+    if options.gradle_version.is_some() && build_system != BuildSystem::Gradle {
+        warn!("--gradle-version only applies to Gradle builds, ignoring it");
+    }
+    match build_system {
+        BuildSystem::Maven => run_maven(project_root, options).map(|()| None),
+        BuildSystem::Gradle => run_gradle(project_root, options).map(|()| None),
+        BuildSystem::Pants => run_pants(project_root, options).map(|()| None),
+        BuildSystem::Buck2 => run_buck2(project_root, options).map(Some),
+        BuildSystem::Custom => anyhow::bail!("Custom builds need a command, see --custom-build"),
+        BuildSystem::Prebuilt => anyhow::bail!("Prebuilt artifacts aren't built"),
+    }
+}
+
+fn run_maven(project_root: &Path, options: &BuildOptions) -> Result<()> {
+    info!("Using Maven");
+
+    let maven = maven_executable(project_root)?;
+
+    // print the maven version by running "mvn version"
+    let mut version = Command::new(&maven);
+    set_java_home(&mut version, options);
+    let output = version
+        .arg("--version")
+        .current_dir(project_root)
+        .output()
+        .map_err(|err| start_failed(maven.as_os_str(), err, options))
+        .context(format!("Failed to run {} --version", maven.display()))?;
+    info!("Maven version: {}", String::from_utf8_lossy(&output.stdout));
+
+    // run "mvn clean package", by default with "-Dmaven.test.skip=true", or
+    // whatever --maven-command asks for with the placeholders filled in
+    let mut expansions: Vec<(&str, Vec<OsString>)> = MAVEN_PLACEHOLDERS
+        .iter()
+        .map(|placeholder| {
+            (
+                *placeholder,
+                maven_placeholder(placeholder, project_root, options),
+            )
+        })
+        .collect();
+    let mut command = Command::new(&maven);
+    for word in options.maven_command.split_whitespace() {
+        match expansions
+            .iter_mut()
+            .find(|(placeholder, _)| *placeholder == word)
+        {
+            Some((_, args)) => {
+                command.args(args.drain(..));
+            }
+            None => {
+                command.arg(word);
+            }
+        }
+    }
+    // The arguments of placeholders used in the template were taken above.
+    // {skip} is left out on purpose to decide on the tests in the template,
+    // other options would silently have no effect
+    for (placeholder, args) in &expansions {
+        if *placeholder != "{skip}" && !args.is_empty() {
+            warn!(
+                "--maven-command has no {} placeholder, ignoring {:?}",
+                placeholder, args
+            );
+        }
+    }
+    if let Some(memory) = &options.memory {
+        add_jvm_option(&mut command, "MAVEN_OPTS", &format!("-Xmx{}", memory));
+    }
+    set_java_home(&mut command, options);
+    command.current_dir(project_root);
+    let output = run_build_process(&mut command, options).context(format!(
+        "Failed to run {} {}",
+        maven.display(),
+        options.maven_command
+    ))?;
+
+    check_build_output("Maven", &output, options, options.offline).map_err(|err| {
+        // Sibling modules are built through -am, so a dependency that still
+        // can't be resolved isn't part of the reactor being built
+        let unresolved = [&output.stdout, &output.stderr].iter().any(|stream| {
+            String::from_utf8_lossy(stream).contains("Could not resolve dependencies")
+        });
+        match &options.maven_module {
+            Some(module) if unresolved => err.context(format!(
+                "Module {} depends on an artifact outside of this reactor, even though its sibling modules were built with -am. Check that the dependency is a module of the root pom.xml, or that it's available from a repository",
+                module
+            )),
+            _ => err,
+        }
+    })?;
+    check_build_warnings("Maven", &output, options, &["[WARNING]"])
+}
+
+/// Returns the Maven to run: the project's Maven Wrapper, which pins the
+/// Maven version the project expects, or else `mvn` from the PATH.
+fn maven_executable(project_root: &Path) -> Result<PathBuf> {
+    let wrapper = project_root.join("mvnw");
+    if wrapper.exists() {
+        make_executable(&wrapper)?;
+        info!("Using Maven Wrapper");
+        Ok(wrapper)
+    } else {
+        Ok(PathBuf::from("mvn"))
+    }
+}
+
+/// Default of `--maven-command`.
+pub(crate) const DEFAULT_MAVEN_COMMAND: &str =
+    "clean package {skip} {offline} {module} {defines} {config} {threads}";
+
+/// Placeholders `--maven-command` may contain, each standing for the Maven
+/// arguments of the corresponding options.
+const MAVEN_PLACEHOLDERS: [&str; 6] = [
+    "{skip}",
+    "{offline}",
+    "{module}",
+    "{defines}",
+    "{config}",
+    "{threads}",
+];
+
+/// Validates a `--maven-command` template: placeholders must be words of
+/// their own and known.
+pub(crate) fn parse_maven_command(template: &str) -> Result<String, String> {
+    if template.trim().is_empty() {
+        return Err("expected Maven arguments, got an empty template".to_string());
+    }
+    if let Some(word) = template.split_whitespace().find(|word| {
+        (word.contains('{') || word.contains('}')) && !MAVEN_PLACEHOLDERS.contains(word)
+    }) {
+        return Err(format!(
+            "unknown placeholder '{}', expected one of {}",
+            word,
+            MAVEN_PLACEHOLDERS.join(", ")
+        ));
+    }
+    Ok(template.to_string())
+}
+
+/// Returns the Maven arguments a `--maven-command` placeholder stands for.
+fn maven_placeholder(
+    placeholder: &str,
+    project_root: &Path,
+    options: &BuildOptions,
+) -> Vec<OsString> {
+    let mut args: Vec<OsString> = Vec::new();
+    match placeholder {
+        "{skip}" => match options.skip_mode {
+            SkipMode::Skip => {
+                info!(
+                    "Tests are neither compiled nor run, use --skip-mode skip-tests to compile them"
+                );
+                args.push("-Dmaven.test.skip=true".into());
+            }
+            SkipMode::SkipTests => args.push("-DskipTests".into()),
+            SkipMode::Run => {}
+        },
+        "{offline}" if options.offline => args.push("-o".into()),
+        "{module}" => {
+            if let Some(module) = &options.maven_module {
+                args.extend(["-pl".into(), format!(":{}", module).into(), "-am".into()]);
+            }
+        }
+        "{defines}" => {
+            args.extend(
+                options
+                    .maven_defines
+                    .iter()
+                    .map(|(key, value)| format!("-D{}={}", key, value).into()),
+            );
+            if let Some(repo) = &options.maven_local_repo {
+                // The build runs in the project root, so the path must not be relative
+                let repo = std::path::absolute(repo).unwrap_or_else(|_| repo.clone());
+                info!("Using local Maven repository: {}", repo.display());
+                let mut arg = OsString::from("-Dmaven.repo.local=");
+                arg.push(repo);
+                args.push(arg);
+            }
+        }
+        "{config}" if options.use_bundled_maven_config => {
+            for (flag, file) in [("-s", "settings.xml"), ("-t", "toolchains.xml")] {
+                match bundled_maven_config(project_root, file) {
+                    Some(path) => {
+                        info!("Using bundled Maven {}: {}", file, path.display());
+                        args.extend([flag.into(), path.into()]);
+                    }
+                    None => info!("No bundled Maven {} found in .mvn/ or ci/", file),
+                }
+            }
+        }
+        "{threads}" => {
+            if let Some(threads) = options.threads {
+                args.extend(["-T".into(), threads.to_string().into()]);
+            }
+        }
+        _ => {}
+    }
+    args
+}
+
+/// Finds a Maven configuration file such as settings.xml that the project
+/// ships in `.mvn/` or `ci/`, for `--use-bundled-maven-config`.
+fn bundled_maven_config(project_root: &Path, file: &str) -> Option<PathBuf> {
+    [".mvn", "ci"]
+        .iter()
+        .map(|dir| project_root.join(dir).join(file))
+        .find(|path| path.is_file())
+}
+
+fn run_gradle(project_root: &Path, options: &BuildOptions) -> Result<()> {
+    info!("Using Gradle");
+
+    // run "./gradlew clean build", by default with "-x check -x test"; excluding
+    // test also skips compiling the tests, as nothing else depends on them
+    let mut command = gradle_command(project_root, options)?;
+    if options.gradle_tasks.is_empty() {
+        command.args(["clean", "build"]);
+    } else {
+        command.args(&options.gradle_tasks);
+    }
+    match options.skip_mode {
+        SkipMode::Skip => {
+            command.args(["-x", "check", "-x", "test"]);
+        }
+        SkipMode::SkipTests => {
+            command.args(["testClasses", "-x", "check", "-x", "test"]);
+        }
+        SkipMode::Run => {}
+    }
+    let output = run_build_process(&mut command, options).context("Failed to run gradlew")?;
+
+    check_build_output("Gradle", &output, options, options.offline)?;
+    check_build_warnings(
+        "Gradle",
+        &output,
+        options,
+        &["Deprecated Gradle features were used", "warning:"],
+    )
+}
+
+/// Prepares the `gradlew` command with the options that apply to every Gradle
+/// invocation, leaving the tasks to the caller.
+fn gradle_command(project_root: &Path, options: &BuildOptions) -> Result<Command> {
+    if let Some(version) = &options.gradle_version {
+        set_gradle_wrapper_version(project_root, version)?;
+    }
+
+    let mut command = Command::new(project_root.join("gradlew"));
+    if options.offline {
+        command.arg("--offline");
+    }
+    if options.no_daemon {
+        command.arg("--no-daemon");
+    }
+    if let Some(threads) = options.threads {
+        command.arg(format!("--max-workers={}", threads));
+    }
+    for (key, value) in &options.gradle_properties {
+        command.arg(format!("-P{}={}", key, value));
+    }
+    if let Some(cache) = &options.gradle_cache {
+        // The build runs in the project root, so the path must not be relative
+        let cache = std::path::absolute(cache)
+            .context(format!("Failed to resolve Gradle cache {:?}", cache))?;
+        // --warmup is what populates the cache in the first place
+        if options.warmup && !cache.exists() {
+            fs::create_dir_all(&cache)
+                .context(format!("Failed to create Gradle cache {:?}", cache))?;
+        }
+        if !cache.is_dir() {
+            anyhow::bail!("Gradle cache {:?} is not a directory", cache);
+        }
+        info!("Using Gradle user home: {}", cache.display());
+        command.env("GRADLE_USER_HOME", cache);
+    }
+    if let Some(memory) = &options.memory {
+        // GRADLE_OPTS only reaches the client; the build itself runs in the
+        // daemon, whose options come from org.gradle.jvmargs
+        add_jvm_option(&mut command, "GRADLE_OPTS", &format!("-Xmx{}", memory));
+        let jvmargs = format!("-Xmx{}", memory);
+        info!("Gradle daemon JVM options: org.gradle.jvmargs={}", jvmargs);
+        command.arg(format!("-Dorg.gradle.jvmargs={}", jvmargs));
+    }
+    set_java_home(&mut command, options);
+    command.current_dir(project_root);
+    Ok(command)
+}
+
+/// Downloads the dependencies of a Maven or Gradle project into the local
+/// repository or Gradle user home without building it, for `--warmup`:
+/// `mvn dependency:go-offline` or `gradlew dependencies`.
+pub(crate) fn run_warmup(
+    build_system: BuildSystem,
+    project_root: &Path,
+    options: &BuildOptions,
+) -> Result<()> {
+    let (tool, mut command) = match build_system {
+        BuildSystem::Maven => {
+            let maven = maven_executable(project_root)?;
+            let mut command = Command::new(&maven);
+            command.arg("dependency:go-offline");
+            for placeholder in ["{module}", "{defines}", "{config}"] {
+                command.args(maven_placeholder(placeholder, project_root, options));
+            }
+            if let Some(memory) = &options.memory {
+                add_jvm_option(&mut command, "MAVEN_OPTS", &format!("-Xmx{}", memory));
+            }
+            set_java_home(&mut command, options);
+            command.current_dir(project_root);
+            ("Maven", command)
+        }
+        BuildSystem::Gradle => {
+            let mut command = gradle_command(project_root, options)?;
+            command.arg("dependencies");
+            ("Gradle", command)
+        }
+        _ => anyhow::bail!(
+            "--warmup only supports Maven and Gradle builds, not {:?}",
+            build_system
+        ),
+    };
+    info!("Downloading the {} dependencies without building", tool);
+    let output = run_build_process(&mut command, options)
+        .context(format!("Failed to run the {} dependency download", tool))?;
+    check_build_output(tool, &output, options, false)
+}
+
+fn run_pants(project_root: &Path, options: &BuildOptions) -> Result<()> {
+    info!("Using Pants");
+
+    // Older repositories ship the `pants` launcher script, newer ones rely on
+    // the `pants` binary installed on the machine
+    let launcher = project_root.join("pants");
+    let pants = if launcher.is_file() {
+        make_executable(&launcher)?;
+        launcher
+    } else {
+        PathBuf::from("pants")
+    };
+
+    // run "pants package ::", with the goal and targets configurable as
+    // addresses are specific to the repository
+    let mut command = Command::new(&pants);
+    if options.skip_mode == SkipMode::Run {
+        command.arg("test");
+    } else {
+        info!("Tests are not run, use --skip-mode run to run them");
+    }
+    command
+        .arg(&options.pants_goal)
+        .args(&options.pants_targets);
+    if let Some(threads) = options.threads {
+        command.arg(format!("--process-execution-local-parallelism={}", threads));
+    }
+    if options.offline {
+        warn!("Pants has no offline mode, ignoring --offline");
+    }
+    if options.memory.is_some() {
+        warn!("--memory only applies to Maven and Gradle, ignoring it");
+    }
+    set_java_home(&mut command, options);
+    command.current_dir(project_root);
+    let output = run_build_process(&mut command, options).context(format!(
+        "Failed to run {} {}",
+        pants.display(),
+        options.pants_goal
+    ))?;
+
+    check_build_output("Pants", &output, options, false)?;
+    check_build_warnings("Pants", &output, options, &["[WARN]"])
+}
+
+fn run_buck2(project_root: &Path, options: &BuildOptions) -> Result<Vec<PathBuf>> {
+    info!("Using Buck2");
+
+    let Some(target) = &options.buck_target else {
+        anyhow::bail!("Buck2 projects need the target to build, pass it with --buck-target");
+    };
+
+    // run "buck2 build <target> --show-output", which prints where each
+    // output ended up below buck-out/
+    let mut command = Command::new("buck2");
+    command.args(["build", target.as_str(), "--show-output"]);
+    if let Some(threads) = options.threads {
+        command.arg(format!("--num-threads={}", threads));
+    }
+    if options.skip_mode == SkipMode::Run {
+        warn!("Running tests is not supported for Buck2, ignoring --skip-mode run");
+    }
+    if options.offline {
+        warn!("Buck2 has no offline mode, ignoring --offline");
+    }
+    if options.memory.is_some() {
+        warn!("--memory only applies to Maven and Gradle, ignoring it");
+    }
+    set_java_home(&mut command, options);
+    command.current_dir(project_root);
+    let output = run_build_process(&mut command, options).context("Failed to run buck2 build")?;
+    check_build_output("Buck2", &output, options, false)?;
+    check_build_warnings("Buck2", &output, options, &["warning:"])?;
+
+    Ok(buck2_outputs(
+        &String::from_utf8_lossy(&output.stdout),
+        project_root,
+    ))
+}
+
+/// Parses the `<target> <path>` lines printed by `buck2 build --show-output`;
+/// the paths are relative to the project root.
+fn buck2_outputs(stdout: &str, project_root: &Path) -> Vec<PathBuf> {
+    stdout
+        .lines()
+        .filter_map(|line| line.trim().split_once(' '))
+        .map(|(_, path)| project_root.join(path.trim()))
+        .collect()
+}
+
+/// Appends a JVM option to an environment variable such as `MAVEN_OPTS`,
+/// keeping the options already set in our environment, and logs the result.
+fn add_jvm_option(command: &mut Command, variable: &str, option: &str) {
+    let value = match env::var(variable) {
+        Ok(existing) if !existing.trim().is_empty() => format!("{} {}", existing, option),
+        _ => option.to_string(),
+    };
+    info!("Build JVM options: {}={}", variable, value);
+    command.env(variable, value);
+}
+
+/// Runs the build with the JDK found with `--project-jdk`, if any, by setting
+/// JAVA_HOME and putting its `bin/` first on the PATH.
+fn set_java_home(command: &mut Command, options: &BuildOptions) {
+    let Some(java_home) = &options.java_home else {
+        return;
+    };
+    command.env("JAVA_HOME", java_home);
+    let path = env::var_os("PATH").unwrap_or_default();
+    let paths = std::iter::once(java_home.join("bin")).chain(env::split_paths(&path));
+    if let Ok(path) = env::join_paths(paths) {
+        command.env("PATH", path);
+    }
+}
+
+/// Parses a JVM heap size such as `512m` or `2g` given with `--memory`.
+pub(crate) fn parse_memory(memory: &str) -> Result<String, String> {
+    let digits = memory.trim_end_matches(['k', 'K', 'm', 'M', 'g', 'G']);
+    if digits.is_empty()
+        || memory.len() - digits.len() > 1
+        || !digits.bytes().all(|byte| byte.is_ascii_digit())
+    {
+        return Err(format!(
+            "expected a heap size like 512m or 2g, got '{}'",
+            memory
+        ));
+    }
+    Ok(memory.to_string())
+}
+
+/// Parses a `KEY=VALUE` property for --maven-define and --gradle-property.
+pub(crate) fn parse_property(property: &str) -> Result<(String, String), String> {
+    match property.split_once('=') {
+        Some((key, value)) if !key.is_empty() && !key.contains(char::is_whitespace) => {
+            Ok((key.to_string(), value.to_string()))
+        }
+        _ => Err(format!("expected KEY=VALUE, got '{}'", property)),
+    }
+}
+
+/// Validates a --gradle-version value, which ends up in the wrapper's
+/// distribution file name.
+pub(crate) fn parse_gradle_version(version: &str) -> Result<String, String> {
+    if version.is_empty()
+        || !version.starts_with(|c: char| c.is_ascii_digit())
+        || !version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+    {
+        return Err(format!(
+            "expected a Gradle version like 8.10.2 or 8.11-rc-1, got '{}'",
+            version
+        ));
+    }
+    Ok(version.to_string())
+}
+
+/// Points the distributionUrl of the project's Gradle wrapper at `version`,
+/// keeping the distribution server and type (bin or all). The checksum of the
+/// original distribution is dropped, as it can't match the new one.
+fn set_gradle_wrapper_version(project_root: &Path, version: &str) -> Result<()> {
+    let properties = project_root.join("gradle/wrapper/gradle-wrapper.properties");
+    if !properties.is_file() {
+        warn!(
+            "--gradle-version given, but the project has no {}, building with the Gradle wrapper as is",
+            properties.display()
+        );
+        return Ok(());
+    }
+    let contents = fs::read_to_string(&properties)
+        .context(format!("Failed to read {}", properties.display()))?;
+
+    let mut rewritten = String::with_capacity(contents.len());
+    let mut original_url = None;
+    for line in contents.lines() {
+        let key = line.split(['=', ':']).next().unwrap_or_default().trim();
+        if key == "distributionSha256Sum" {
+            continue;
+        }
+        if key == "distributionUrl" {
+            let (prefix, url) = line.split_at(line.find(['=', ':']).unwrap_or_default() + 1);
+            let (base, file_name) = url.rsplit_once('/').unwrap_or(("", url));
+            let kind = file_name
+                .strip_prefix("gradle-")
+                .and_then(|name| name.strip_suffix(".zip"))
+                .and_then(|name| name.rsplit_once('-'))
+                .map(|(_, kind)| kind)
+                .filter(|kind| matches!(*kind, "bin" | "all"))
+                .with_context(|| {
+                    format!(
+                        "Can't set the Gradle version, unexpected distributionUrl in {}: {}",
+                        properties.display(),
+                        url.trim()
+                    )
+                })?;
+            let slash = if base.is_empty() { "" } else { "/" };
+            rewritten.push_str(&format!(
+                "{}{}{}gradle-{}-{}.zip",
+                prefix, base, slash, version, kind
+            ));
+            original_url = Some(url.trim().to_string());
+        } else {
+            rewritten.push_str(line);
+        }
+        rewritten.push('\n');
+    }
+    let original_url = original_url.with_context(|| {
+        format!(
+            "Can't set the Gradle version, {} has no distributionUrl",
+            properties.display()
+        )
+    })?;
+
+    fs::write(&properties, rewritten)
+        .context(format!("Failed to write {}", properties.display()))?;
+    warn!(
+        "--gradle-version overrides the project's Gradle wrapper: building with Gradle {} instead of {}",
+        version, original_url
+    );
+    Ok(())
+}
+
+/// Runs a user-supplied build command through the shell in the project root.
+pub(crate) fn run_custom_build(
+    command: &str,
+    project_root: &Path,
+    options: &BuildOptions,
+) -> Result<()> {
+    info!("Running custom build: {}", command);
+
+    let mut process = Command::new("sh");
+    process.args(["-c", command]).current_dir(project_root);
+    set_java_home(&mut process, options);
+    let output = run_build_process(&mut process, options)
+        .context(format!("Failed to run custom build: {}", command))?;
+
+    check_build_output("Custom", &output, options, false)?;
+    check_build_warnings("Custom", &output, options, &["[WARNING]", "warning:"])
+}
+
+/// Runs a build process to completion, capturing its output, and registers it
+/// so that [`terminate_build`] can stop it.
+fn run_build_process(command: &mut Command, options: &BuildOptions) -> io::Result<Output> {
+    debug!("Running {:?}", command);
+    if options.dump_env {
+        dump_env(command);
+    }
+    // Run the build in its own process group, so that terminating it also
+    // reaches the JVM started by wrapper scripts
+    let child = command
+        .process_group(0)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| start_failed(command.get_program(), err, options))?;
+    *BUILD_PROCESS.lock().unwrap() = Some(child.id());
+    let output = child.wait_with_output();
+    *BUILD_PROCESS.lock().unwrap() = None;
+    output
+}
+
+/// With `--error-output`, records that the build tool `program` couldn't be
+/// started in the failure log. Returns the error for the caller to report.
+fn start_failed(program: &OsStr, err: io::Error, options: &BuildOptions) -> io::Error {
+    if options.error_output {
+        let summary = format!("Failed to start {}: {}", program.to_string_lossy(), err);
+        write_failure_log(&summary, None, &options.output_dir);
+    }
+    err
+}
+
+/// Parts of environment variable names that mark their value as a secret.
+const SECRET_NAME_PARTS: [&str; 5] = ["SECRET", "PASSWORD", "PASSWD", "CREDENTIAL", "PRIVATE"];
+
+/// Whether the value of an environment variable is likely a secret, judging
+/// by its name: `GITHUB_TOKEN`, `NEXUS_PASSWORD`, `AWS_SECRET_ACCESS_KEY`,
+/// `ORG_GRADLE_PROJECT_signingKey`, ... Words are matched as a whole where
+/// they occur in harmless names too, like `TOKEN` in `MAX_TOKENS`.
+fn is_secret_name(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    SECRET_NAME_PARTS.iter().any(|part| name.contains(part))
+        || name.split('_').any(|word| {
+            word.ends_with("KEY")
+                || word.ends_with("TOKEN")
+                || matches!(word, "AUTH" | "COOKIE" | "PASS" | "PAT")
+        })
+}
+
+/// With `--dump-env`, prints the environment the build process receives: the
+/// one of monteur with the variables set for the build, with the values of
+/// likely secrets redacted.
+fn dump_env(command: &Command) {
+    let mut vars: BTreeMap<OsString, OsString> = env::vars_os().collect();
+    for (name, value) in command.get_envs() {
+        match value {
+            Some(value) => vars.insert(name.to_os_string(), value.to_os_string()),
+            None => vars.remove(name),
+        };
+    }
+    let lines: Vec<String> = vars
+        .iter()
+        .map(|(name, value)| {
+            let name = name.to_string_lossy();
+            if is_secret_name(&name) {
+                format!("  {}=<redacted>", name)
+            } else {
+                format!("  {}={}", name, value.to_string_lossy())
+            }
+        })
+        .collect();
+    info!("Build environment:\n{}", lines.join("\n"));
+}
+
+/// Terminates the running build process, if any, and waits a few seconds for
+/// it to exit. Meant to be called from a signal handler on interruption.
+pub fn terminate_build() {
+    let Some(pid) = *BUILD_PROCESS.lock().unwrap() else {
+        return;
+    };
+    info!("Terminating build process {}", pid);
+    if let Err(err) = signal::killpg(Pid::from_raw(pid as i32), Signal::SIGTERM) {
+        warn!("Failed to terminate build process {}: {}", pid, err);
+        return;
+    }
+
+    // The process is reaped, and unregistered, by the thread waiting for it
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while BUILD_PROCESS.lock().unwrap().is_some() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Prints the output of a build and fails if it didn't succeed.
+fn check_build_output(
+    tool: &str,
+    output: &Output,
+    options: &BuildOptions,
+    offline: bool,
+) -> Result<()> {
+    info!(
+        "{} build output:\n{}{}",
+        tool,
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    if !output.status.success() {
+        if options.error_output {
+            let summary = format!("{} build failed with {}", tool, output.status);
+            write_failure_log(&summary, Some(output), &options.output_dir);
+        }
+        if offline {
+            anyhow::bail!(
+                "{} build failed with {}. It ran with --offline, so check that every dependency is present in the local repository",
+                tool,
+                output.status
+            );
+        }
+        anyhow::bail!("{} build failed with {}", tool, output.status);
+    }
+    Ok(())
+}
+
+/// Name of the file `--error-output` writes the output of a failed build to.
+const FAILURE_LOG_FILE: &str = "monteur-build-failure.log";
+
+/// Writes why the build failed, followed by its output if it ran, to the
+/// output directory, where CI collects artifacts from even when the run
+/// failed. Not being able to write it mustn't hide the build failure, so that
+/// is only a warning.
+fn write_failure_log(summary: &str, output: Option<&Output>, output_dir: &Path) {
+    let path = output_dir.join(FAILURE_LOG_FILE);
+    let log = match output {
+        Some(output) => format!(
+            "{}\n\n--- stdout ---\n{}\n--- stderr ---\n{}",
+            summary,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        ),
+        None => format!("{}\n", summary),
+    };
+    match fs::create_dir_all(output_dir).and_then(|()| fs::write(&path, log)) {
+        Ok(()) => info!("Wrote the build output to {}", path.display()),
+        Err(err) => warn!(
+            "Failed to write the build output to {}: {}",
+            path.display(),
+            err
+        ),
+    }
+}
+
+/// With `--fail-on-build-warning`, fails if a line of the build output contains
+/// one of the `--build-warning-pattern`s, or else one of `default_patterns`.
+fn check_build_warnings(
+    tool: &str,
+    output: &Output,
+    options: &BuildOptions,
+    default_patterns: &[&str],
+) -> Result<()> {
+    if !options.fail_on_build_warning {
+        return Ok(());
+    }
+    let patterns: Vec<&str> = if options.build_warning_patterns.is_empty() {
+        default_patterns.to_vec()
+    } else {
+        options
+            .build_warning_patterns
+            .iter()
+            .map(String::as_str)
+            .collect()
+    };
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let warnings: Vec<_> = stdout
+        .lines()
+        .chain(stderr.lines())
+        .filter(|line| patterns.iter().any(|pattern| line.contains(pattern)))
+        .collect();
+    if !warnings.is_empty() {
+        // The full output was logged already, keep the error readable
+        let mut listed = warnings[..warnings.len().min(20)].join("\n");
+        if warnings.len() > 20 {
+            listed.push_str(&format!("\n... and {} more", warnings.len() - 20));
+        }
+        let summary = format!(
+            "{} build emitted {} warnings and --fail-on-build-warning was given",
+            tool,
+            warnings.len()
+        );
+        if options.error_output {
+            write_failure_log(&summary, Some(output), &options.output_dir);
+        }
+        anyhow::bail!("{}:\n{}", summary, listed);
+    }
+    Ok(())
+}
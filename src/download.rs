@@ -0,0 +1,880 @@
+use crate::BuildOptions;
+use crate::archive::{
+    ArchiveFormat, ArchiveSource, detect_archive_format, detect_format_from_magic, sniff_format,
+};
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use log::{info, warn};
+use pgp::composed::{Deserializable, SignedPublicKey, StandaloneSignature};
+use reqwest::blocking::{Body, Client, Response};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::redirect::Policy;
+use reqwest::{Certificate, StatusCode};
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Write, copy};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use suppaftp::types::FileType;
+use suppaftp::{FtpError, FtpStream};
+
+/// A downloaded source archive.
+pub(crate) struct DownloadedArchive {
+    /// Where the archive was saved, unless it is kept in memory
+    pub(crate) path: PathBuf,
+    /// Contents of an archive below `--in-memory-limit`, which isn't saved
+    pub(crate) contents: Option<Vec<u8>>,
+    pub(crate) format: ArchiveFormat,
+    /// Name of the file as announced by the server or taken from the URL
+    pub(crate) file_name: Option<String>,
+}
+
+impl DownloadedArchive {
+    pub(crate) fn source(&self) -> ArchiveSource<'_> {
+        match &self.contents {
+            Some(contents) => ArchiveSource::Memory(contents),
+            None => ArchiveSource::File(&self.path),
+        }
+    }
+}
+
+/// Downloads the source archive into `temp_dir_path`, detecting its format.
+/// Archives that announce a size up to `--in-memory-limit` are kept in memory
+/// instead.
+pub(crate) fn download_archive(
+    client: &Client,
+    options: &BuildOptions,
+    temp_dir_path: &Path,
+) -> Result<DownloadedArchive> {
+    let download_url = &options.url;
+    if is_ftp_url(download_url) {
+        if options.preflight {
+            warn!("--preflight only applies to HTTP downloads, ignoring it");
+        }
+        return download_ftp_archive(options, temp_dir_path);
+    }
+    if options.preflight {
+        preflight(client, download_url)?;
+    }
+
+    // Download the archive
+    info!("Downloading from: {}", download_url);
+    let mut attempt = 0;
+    let response = loop {
+        let response = client
+            .get(download_url)
+            .send()
+            .context("Failed to download file")?;
+        if response.status() != StatusCode::TOO_MANY_REQUESTS || attempt >= options.retries {
+            break response;
+        }
+
+        // Back off exponentially if the server doesn't say how long to wait
+        attempt += 1;
+        let wait = retry_after(&response).unwrap_or(Duration::from_secs(1 << attempt.min(6)));
+        info!(
+            "Rate limited (HTTP 429), retrying in {}s (attempt {}/{})",
+            wait.as_secs(),
+            attempt,
+            options.retries
+        );
+        thread::sleep(wait);
+    };
+
+    if !response.status().is_success() {
+        anyhow::bail!("Failed to download file: HTTP status {}", response.status());
+    }
+
+    // Guess the archive format from the filename announced by the server, or
+    // else from the URL path, unless it was given explicitly
+    let disposition_filename = response
+        .headers()
+        .get(reqwest::header::CONTENT_DISPOSITION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(content_disposition_filename);
+    if let Some(filename) = &disposition_filename {
+        info!("Server provided filename: {}", filename);
+    }
+    let url_path = reqwest::Url::parse(download_url)
+        .map(|url| url.path().to_string())
+        .unwrap_or_else(|_| download_url.clone());
+    let name_format = options.archive_format.or_else(|| {
+        disposition_filename
+            .as_deref()
+            .and_then(detect_archive_format)
+            .or_else(|| detect_archive_format(&url_path))
+    });
+
+    // Without a Content-Length, e.g. with chunked transfer encoding, the body
+    // is read until the server ends it; a truncated body can't be told apart
+    // then, whereas the HTTP client fails on one shorter than announced
+    if response.content_length().is_none() {
+        info!(
+            "The server didn't announce the archive size, reading it until the end of the response"
+        );
+    }
+
+    // The archive is saved under the name the server announced, if it is
+    // usable as a file name in the work directory
+    let archive_path = match disposition_filename
+        .as_deref()
+        .and_then(file_name_component)
+    {
+        Some(name) => temp_dir_path.join(name),
+        None => archive_file_path(temp_dir_path, name_format),
+    };
+    // Small archives skip the round trip through the disk. Only the length of
+    // this response counts, the server may send something else than it
+    // announced in a preflight request
+    let in_memory = options.in_memory_limit > 0
+        && response
+            .content_length()
+            .is_some_and(|length| length <= options.in_memory_limit);
+    let gzip_encoded = is_gzip_encoded(&response);
+    let contents = if in_memory {
+        info!("Keeping the archive in memory");
+        let mut contents = Vec::new();
+        save_response(response, &mut contents, options)?;
+        if gzip_encoded && wraps_gzip(contents.as_slice()) {
+            info!("Removing the gzip content encoding of the response");
+            let mut decoded = Vec::new();
+            GzDecoder::new(contents.as_slice())
+                .read_to_end(&mut decoded)
+                .context("Failed to decode the gzip-encoded response")?;
+            contents = decoded;
+        }
+        Some(contents)
+    } else {
+        let mut archive_file =
+            File::create(&archive_path).context("Failed to create temporary archive file")?;
+        save_response(response, &mut archive_file, options)?;
+        let archive_file = File::open(&archive_path).context("Failed to open archive file")?;
+        if gzip_encoded && wraps_gzip(archive_file) {
+            info!("Removing the gzip content encoding of the response");
+            decode_gzip_file(&archive_path)?;
+        }
+        None
+    };
+
+    downloaded_archive(
+        options,
+        archive_path,
+        contents,
+        name_format,
+        disposition_filename.as_deref().unwrap_or(&url_path),
+    )
+}
+
+/// Default of `--repository`.
+pub(crate) const MAVEN_CENTRAL: &str = "https://repo1.maven.org/maven2";
+
+/// Validates a `--coordinate` of the form
+/// `GROUP:ARTIFACT:VERSION[:CLASSIFIER[:EXTENSION]]`.
+pub(crate) fn parse_coordinate(coordinate: &str) -> Result<String, String> {
+    let parts: Vec<_> = coordinate.split(':').collect();
+    if !(3..=5).contains(&parts.len())
+        || parts
+            .iter()
+            .any(|part| part.is_empty() || part.contains(['/', '\\']) || part.starts_with('.'))
+    {
+        return Err(format!(
+            "expected GROUP:ARTIFACT:VERSION[:CLASSIFIER[:EXTENSION]], got '{}'",
+            coordinate
+        ));
+    }
+    Ok(coordinate.to_string())
+}
+
+/// Builds the URL of the artifact a `--coordinate` names in the Maven
+/// repository at `repository`, following the standard repository layout.
+pub(crate) fn coordinate_url(coordinate: &str, repository: &str) -> String {
+    let parts: Vec<_> = coordinate.split(':').collect();
+    let (group, artifact, version) = (parts[0], parts[1], parts[2]);
+    let classifier = parts.get(3).copied().unwrap_or("sources");
+    let extension = parts.get(4).copied().unwrap_or("jar");
+    format!(
+        "{}/{}/{}/{}/{}-{}-{}.{}",
+        repository.trim_end_matches('/'),
+        group.replace('.', "/"),
+        artifact,
+        version,
+        artifact,
+        version,
+        classifier,
+        extension
+    )
+}
+
+/// Checks the URL with a HEAD request for `--preflight`, failing on an error
+/// status, and logs the size of the download, if the server announces it.
+/// Servers that don't support HEAD, or rate limit it, don't fail the run.
+fn preflight(client: &Client, url: &str) -> Result<()> {
+    info!("Checking the URL with a HEAD request");
+    let response = client
+        .head(url)
+        .send()
+        .context("Preflight HEAD request failed")?;
+    let status = response.status();
+    if matches!(
+        status,
+        StatusCode::METHOD_NOT_ALLOWED
+            | StatusCode::NOT_IMPLEMENTED
+            | StatusCode::TOO_MANY_REQUESTS
+    ) {
+        warn!(
+            "The server answered the HEAD request with HTTP status {}, skipping the preflight check",
+            status
+        );
+        return Ok(());
+    }
+    if matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) {
+        anyhow::bail!(
+            "Preflight HEAD request failed: HTTP status {}, check the credentials passed with --header or --headers-file",
+            status
+        );
+    }
+    if !status.is_success() {
+        anyhow::bail!("Preflight HEAD request failed: HTTP status {}", status);
+    }
+
+    // The body of a HEAD response is empty, so its size is only in the header
+    let headers = response.headers();
+    let length = headers
+        .get(reqwest::header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    let content_type = headers
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+    info!(
+        "Preflight: HTTP status {}, {}, {}",
+        status,
+        length.map_or("unknown size".to_string(), |length| format!(
+            "{} bytes",
+            length
+        )),
+        content_type.unwrap_or("unknown content type")
+    );
+    if content_type.is_some_and(|content_type| content_type.starts_with("text/html")) {
+        warn!(
+            "The URL serves an HTML page, which is likely not the archive but e.g. a login or download page"
+        );
+    }
+    Ok(())
+}
+
+/// Returns whether the server announced `Content-Encoding: gzip`.
+///
+/// reqwest is built without automatic decompression, as servers often send a
+/// `.tar.gz` with that header although the body is the file itself. The body
+/// is kept as it was served then, which is also what a signature was made
+/// for; see [`wraps_gzip`] for when the encoding is removed.
+fn is_gzip_encoded(response: &Response) -> bool {
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|encoding| {
+            encoding.eq_ignore_ascii_case("gzip") || encoding.eq_ignore_ascii_case("x-gzip")
+        })
+}
+
+/// Returns whether a gzip-encoded body is a gzipped file compressed once more
+/// by the server, whose encoding must be removed to get the file itself.
+fn wraps_gzip(body: impl Read) -> bool {
+    let mut magic = [0; 2];
+    GzDecoder::new(body)
+        .read_exact(&mut magic)
+        .is_ok_and(|()| magic == [0x1f, 0x8b])
+}
+
+/// Replaces the gzip-encoded body saved at `path` with its decoded contents.
+fn decode_gzip_file(path: &Path) -> Result<()> {
+    let decoded_path = path.with_file_name(".monteur-decoding");
+    let mut decoder = GzDecoder::new(File::open(path).context("Failed to open archive file")?);
+    let mut decoded =
+        File::create(&decoded_path).context(format!("Failed to create {:?}", decoded_path))?;
+    copy(&mut decoder, &mut decoded).context("Failed to decode the gzip-encoded response")?;
+    fs::rename(&decoded_path, path)
+        .context(format!("Failed to move {:?} to {:?}", decoded_path, path))
+}
+
+/// Saves the body of the response, honoring `--stall-timeout`.
+fn save_response(
+    response: Response,
+    writer: &mut impl Write,
+    options: &BuildOptions,
+) -> Result<()> {
+    match options.stall_timeout {
+        Some(seconds) => {
+            copy_with_stall_timeout(response, writer, Duration::from_secs(seconds))?;
+        }
+        None => {
+            let mut response = response;
+            copy(&mut response, writer).context("Failed to save archive")?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether the source is downloaded over FTP rather than HTTP.
+fn is_ftp_url(url: &str) -> bool {
+    url.get(..6)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("ftp://"))
+}
+
+/// Downloads the source archive from an FTP server, logging in with the user
+/// and password of the URL, or anonymously if it has none.
+fn download_ftp_archive(options: &BuildOptions, temp_dir_path: &Path) -> Result<DownloadedArchive> {
+    let url = reqwest::Url::parse(&options.url).context("Invalid FTP URL")?;
+    let host = url.host_str().context("FTP URL has no host")?;
+    let port = url.port().unwrap_or(21);
+    let (user, password) = if url.username().is_empty() {
+        ("anonymous".to_string(), "anonymous@".to_string())
+    } else {
+        (
+            percent_decode(url.username()),
+            percent_decode(url.password().unwrap_or_default()),
+        )
+    };
+    // The path is relative to the directory the user is logged in to
+    let url_path = percent_decode(url.path());
+    let remote_path = url_path.trim_start_matches('/');
+
+    let mut display_url = url.clone();
+    if url.password().is_some() {
+        let _ = display_url.set_password(Some("***"));
+    }
+    info!("Downloading from: {}", display_url);
+    if !options.headers.is_empty() || options.headers_file.is_some() {
+        warn!("HTTP headers aren't sent to FTP servers, pass the credentials in the URL instead");
+    }
+
+    let mut ftp = FtpStream::connect((host, port))
+        .context(format!("Failed to connect to FTP server {}:{}", host, port))?;
+    if let Some(seconds) = options.stall_timeout {
+        // Blocking reads on the data connection fail once it stalls
+        let timeout = Some(Duration::from_secs(seconds));
+        ftp.get_ref()
+            .set_read_timeout(timeout)
+            .context("Failed to set FTP timeout")?;
+        ftp = ftp.passive_stream_builder(move |address| {
+            let stream = TcpStream::connect(address).map_err(FtpError::ConnectionError)?;
+            stream
+                .set_read_timeout(timeout)
+                .map_err(FtpError::ConnectionError)?;
+            Ok(stream)
+        });
+    }
+    ftp.login(user.as_str(), password.as_str())
+        .context("FTP login failed")?;
+    ftp.transfer_type(FileType::Binary)
+        .context("Failed to switch FTP to binary mode")?;
+
+    let name_format = options
+        .archive_format
+        .or_else(|| detect_archive_format(&url_path));
+    let archive_path = archive_file_path(temp_dir_path, name_format);
+    let mut archive_file =
+        File::create(&archive_path).context("Failed to create temporary archive file")?;
+    ftp.retr(remote_path, |reader| {
+        copy(reader, &mut archive_file).map_err(FtpError::ConnectionError)
+    })
+    .map_err(|err| match err {
+        FtpError::ConnectionError(err)
+            if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+        {
+            anyhow::anyhow!(
+                "Download stalled: no data received for {}s",
+                options.stall_timeout.unwrap_or_default()
+            )
+        }
+        err => anyhow::Error::new(err),
+    })
+    .context("Failed to download file")?;
+    // The archive is complete, so a failure to log out doesn't matter
+    let _ = ftp.quit();
+
+    downloaded_archive(options, archive_path, None, name_format, &url_path)
+}
+
+/// Path of the file the archive is downloaded to, with an extension matching
+/// its expected format.
+fn archive_file_path(temp_dir_path: &Path, name_format: Option<ArchiveFormat>) -> PathBuf {
+    temp_dir_path.join(match name_format {
+        Some(format) => format!("archive.{}", format),
+        None => "archive".to_string(),
+    })
+}
+
+/// Returns the last component of a file name or URL path, as a server could
+/// announce e.g. `../name`. Hidden names are rejected too, so that the file
+/// can't collide with the directories extraction uses in the work directory.
+fn file_name_component(name: &str) -> Option<&str> {
+    name.rsplit(['/', '\\'])
+        .next()
+        .filter(|name| !name.is_empty() && !name.starts_with('.'))
+}
+
+/// Detects the format of the downloaded archive, saved at `archive_path`
+/// unless its `contents` are kept in memory; `name` is the file name or URL
+/// path it was downloaded as.
+fn downloaded_archive(
+    options: &BuildOptions,
+    archive_path: PathBuf,
+    contents: Option<Vec<u8>>,
+    name_format: Option<ArchiveFormat>,
+    name: &str,
+) -> Result<DownloadedArchive> {
+    // The magic bytes of the downloaded file are more reliable than the URL,
+    // but an explicitly given format always wins
+    let magic_format = match &contents {
+        Some(contents) => sniff_format(&contents[..contents.len().min(512)]),
+        None => detect_format_from_magic(&archive_path)?,
+    };
+    let format = match options.archive_format {
+        Some(format) => format,
+        None => magic_format
+            .or(name_format)
+            .context("Could not detect the archive format, use --archive-format to specify it")?,
+    };
+
+    let file_name = file_name_component(name).map(str::to_string);
+
+    Ok(DownloadedArchive {
+        path: archive_path,
+        contents,
+        format,
+        file_name,
+    })
+}
+
+/// Creates the HTTP client shared by all downloads and uploads of a run, so
+/// that connections are reused; HTTP/2 is used where the server supports it.
+/// It sends the headers given with `--header` and `--headers-file` on every
+/// request and trusts the `--cacert` certificates in addition to the system's.
+pub(crate) fn http_client(options: &BuildOptions) -> Result<Client> {
+    let mut headers = HeaderMap::new();
+    if let Some(path) = &options.headers_file {
+        for (name, value) in read_headers_file(path)? {
+            headers.append(name, value);
+        }
+    }
+    for (name, value) in &options.headers {
+        headers.append(name.clone(), value.clone());
+    }
+
+    let mut builder = Client::builder()
+        .user_agent(&options.user_agent)
+        .default_headers(headers)
+        .redirect(redirect_policy(options.max_redirects))
+        .danger_accept_invalid_certs(options.insecure);
+    if let Some(path) = &options.cacert {
+        let pem = fs::read(path).context(format!("Failed to read CA certificate {:?}", path))?;
+        for certificate in Certificate::from_pem_bundle(&pem)
+            .context(format!("Failed to parse CA certificate {:?}", path))?
+        {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    builder.build().context("Failed to create HTTP client")
+}
+
+/// Follows up to `max_redirects` redirects. Running into the limit or into a
+/// URL that was already visited fails with the redirect chain, which is what
+/// it takes to fix a broken release URL.
+fn redirect_policy(max_redirects: usize) -> Policy {
+    Policy::custom(move |attempt| {
+        let chain = || {
+            attempt
+                .previous()
+                .iter()
+                .chain([attempt.url()])
+                .map(|url| format!("  {}", url))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        if attempt.previous().contains(attempt.url()) {
+            let error = format!("Redirect loop:\n{}", chain());
+            attempt.error(error)
+        } else if attempt.previous().len() > max_redirects {
+            let error = format!(
+                "Too many redirects (possible loop), more than {}:\n{}",
+                max_redirects,
+                chain()
+            );
+            attempt.error(error)
+        } else {
+            attempt.follow()
+        }
+    })
+}
+
+/// Streams an artifact to `upload_url` with an HTTP PUT, sending the same
+/// headers as the download. If the URL ends with `/`, the artifact's file
+/// name is appended to it.
+pub(crate) fn upload_artifact(client: &Client, path: &Path, upload_url: &str) -> Result<()> {
+    let url = if upload_url.ends_with('/') {
+        let file_name = path
+            .file_name()
+            .context(format!("{:?} has no file name", path))?
+            .to_string_lossy();
+        format!("{}{}", upload_url, file_name)
+    } else {
+        upload_url.to_string()
+    };
+    info!("Uploading {} to: {}", path.display(), url);
+
+    let file = File::open(path).context(format!("Failed to open {:?}", path))?;
+    let size = file
+        .metadata()
+        .context(format!("Failed to stat {:?}", path))?
+        .len();
+    let response = client
+        .put(&url)
+        .body(Body::sized(file, size))
+        .send()
+        .context(format!("Failed to upload {:?}", path))?;
+
+    if !response.status().is_success() {
+        anyhow::bail!(
+            "Failed to upload {:?}: HTTP status {}",
+            path,
+            response.status()
+        );
+    }
+    info!("Uploaded {} ({})", path.display(), response.status());
+    Ok(())
+}
+
+/// Parses a `Name: Value` HTTP header given on the command line.
+pub(crate) fn parse_header(header: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = header
+        .split_once(':')
+        .ok_or_else(|| format!("expected 'Name: Value', got '{}'", header))?;
+    let name = HeaderName::try_from(name.trim())
+        .map_err(|err| format!("invalid header name '{}': {}", name.trim(), err))?;
+    let mut value = HeaderValue::try_from(value.trim())
+        .map_err(|err| format!("invalid value for header '{}': {}", name, err))?;
+    // Keep tokens out of debug output
+    value.set_sensitive(true);
+    Ok((name, value))
+}
+
+/// Reads `Name: Value` headers from a file, one per line. Blank lines and
+/// lines starting with `#` are ignored.
+fn read_headers_file(path: &Path) -> Result<Vec<(HeaderName, HeaderValue)>> {
+    let contents =
+        fs::read_to_string(path).context(format!("Failed to read headers file {:?}", path))?;
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(index, line)| {
+            parse_header(line)
+                .map_err(anyhow::Error::msg)
+                .context(format!(
+                    "Malformed header on line {} of {:?}",
+                    index + 1,
+                    path
+                ))
+        })
+        .collect()
+}
+
+/// Downloads the detached OpenPGP signature and verifies it over the archive
+/// with the given public key (ASCII-armored or binary). Signatures made by a
+/// subkey are accepted as well.
+pub(crate) fn verify_signature(
+    client: &Client,
+    archive: ArchiveSource,
+    signature_url: &str,
+    pubkey_path: &Path,
+) -> Result<()> {
+    info!("Downloading signature from: {}", signature_url);
+    let signature = client
+        .get(signature_url)
+        .send()
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.bytes())
+        .context("Failed to download signature")?;
+    let (signature, _) = StandaloneSignature::from_reader_single(signature.as_ref())
+        .context("Failed to parse signature")?;
+
+    let pubkey_file =
+        File::open(pubkey_path).context(format!("Failed to open public key {:?}", pubkey_path))?;
+    let (public_key, _) =
+        SignedPublicKey::from_reader_single(pubkey_file).context("Failed to parse public key")?;
+
+    let open_archive = || archive.open();
+    let mut verified = signature
+        .signature
+        .verify(&public_key.primary_key, open_archive()?)
+        .is_ok();
+    for subkey in &public_key.public_subkeys {
+        if verified {
+            break;
+        }
+        verified = signature
+            .signature
+            .verify(&subkey.key, open_archive()?)
+            .is_ok();
+    }
+
+    if !verified {
+        anyhow::bail!(
+            "Signature verification failed: the archive was not signed by {:?}",
+            pubkey_path
+        );
+    }
+    info!("Signature verified");
+    Ok(())
+}
+
+/// Extracts the filename from a `Content-Disposition` header value, preferring
+/// the RFC 5987 `filename*` parameter over the plain `filename` parameter.
+fn content_disposition_filename(value: &str) -> Option<String> {
+    let mut filename = None;
+    for (key, param_value) in header_parameters(value) {
+        match key.as_str() {
+            "filename*" => {
+                // Format: charset'language'percent-encoded-name
+                let encoded = param_value.splitn(3, '\'').nth(2)?;
+                let decoded = percent_decode(encoded);
+                if !decoded.is_empty() {
+                    return Some(decoded);
+                }
+            }
+            "filename" if !param_value.is_empty() => filename = Some(param_value),
+            _ => {}
+        }
+    }
+    filename
+}
+
+/// Splits the parameters following the first value of a header such as
+/// `attachment; filename="a;b.zip"` into lowercase keys and their values.
+/// Quoted values are unquoted, so they may contain `;`.
+fn header_parameters(value: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let Some((_, mut rest)) = value.split_once(';') else {
+        return params;
+    };
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ';' || c.is_whitespace());
+        if rest.is_empty() {
+            return params;
+        }
+        let key_end = rest.find(['=', ';']).unwrap_or(rest.len());
+        let key = rest[..key_end].trim().to_ascii_lowercase();
+        // A parameter without a value is skipped
+        let Some(after) = rest[key_end..].strip_prefix('=') else {
+            rest = &rest[key_end..];
+            continue;
+        };
+        let after = after.trim_start();
+        let param_value = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let mut unquoted = String::new();
+                let mut end = quoted.len();
+                let mut chars = quoted.char_indices();
+                while let Some((index, c)) = chars.next() {
+                    match c {
+                        '"' => {
+                            end = index + 1;
+                            break;
+                        }
+                        '\\' => unquoted.extend(chars.next().map(|(_, escaped)| escaped)),
+                        c => unquoted.push(c),
+                    }
+                }
+                rest = &quoted[end..];
+                unquoted
+            }
+            None => {
+                let end = after.find(';').unwrap_or(after.len());
+                rest = &after[end..];
+                after[..end].trim().to_string()
+            }
+        };
+        params.push((key, param_value));
+    }
+}
+
+/// Decodes `%XX` escapes, leaving malformed escapes untouched.
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match bytes[i] {
+            b'%' => input
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Returns how long the server asked us to wait via the `Retry-After` header,
+/// given either in seconds or as an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    match value.parse::<u64>() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => {
+            let date = httpdate::parse_http_date(value).ok()?;
+            // A date in the past means we may retry right away
+            Some(
+                date.duration_since(SystemTime::now())
+                    .unwrap_or(Duration::ZERO),
+            )
+        }
+    }
+}
+
+/// Streams the response body into `writer`, failing if no data arrives for
+/// `stall_timeout`. The body is read on a separate thread because a blocking
+/// read on a hung connection can't be interrupted.
+fn copy_with_stall_timeout(
+    mut response: Response,
+    writer: &mut impl Write,
+    stall_timeout: Duration,
+) -> Result<u64> {
+    let (sender, receiver) = mpsc::sync_channel::<std::io::Result<Vec<u8>>>(16);
+    thread::spawn(move || {
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let chunk = match response.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => Ok(buffer[..read].to_vec()),
+                Err(err) => Err(err),
+            };
+            let failed = chunk.is_err();
+            if sender.send(chunk).is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    let mut received = 0;
+    loop {
+        match receiver.recv_timeout(stall_timeout) {
+            Ok(Ok(chunk)) => {
+                writer.write_all(&chunk).context("Failed to save archive")?;
+                received += chunk.len() as u64;
+            }
+            Ok(Err(err)) => return Err(err).context("Failed to read response"),
+            Err(RecvTimeoutError::Disconnected) => return Ok(received),
+            Err(RecvTimeoutError::Timeout) => anyhow::bail!(
+                "Download stalled: no data received for {}s after {} bytes",
+                stall_timeout.as_secs(),
+                received
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_disposition_filename_parses_quoted_values() {
+        assert_eq!(
+            content_disposition_filename(r#"attachment; filename="a;b \"c\".zip"; size=3"#),
+            Some(r#"a;b "c".zip"#.to_string())
+        );
+        assert_eq!(
+            content_disposition_filename("attachment; filename=project.tar.gz"),
+            Some("project.tar.gz".to_string())
+        );
+        assert_eq!(
+            content_disposition_filename(
+                r#"attachment; filename="fallback.zip"; filename*=UTF-8''na%C3%AFve.zip"#
+            ),
+            Some("naïve.zip".to_string())
+        );
+        assert_eq!(content_disposition_filename("inline"), None);
+        assert_eq!(
+            content_disposition_filename(r#"attachment; filename="""#),
+            None
+        );
+    }
+
+    #[test]
+    fn file_name_component_strips_directories() {
+        assert_eq!(file_name_component("../../etc/app.zip"), Some("app.zip"));
+        assert_eq!(file_name_component(r"C:\dist\app.zip"), Some("app.zip"));
+        assert_eq!(file_name_component("/downloads/"), None);
+        assert_eq!(file_name_component(".."), None);
+        assert_eq!(file_name_component(".monteur-extracting"), None);
+    }
+
+    /// Serves `body` once to a GET request, without a `Content-Length`, after
+    /// announcing its length to a HEAD request, and returns the URL.
+    fn serve_without_content_length(body: &'static [u8]) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/app.zip", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut byte = [0];
+                while !request.ends_with(b"\r\n\r\n") {
+                    stream.read_exact(&mut byte).unwrap();
+                    request.push(byte[0]);
+                }
+                if request.starts_with(b"HEAD") {
+                    let head = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    );
+                    stream.write_all(head.as_bytes()).unwrap();
+                } else {
+                    stream
+                        .write_all(b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n")
+                        .unwrap();
+                    stream.write_all(body).unwrap();
+                    return;
+                }
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn download_without_content_length_is_saved_to_disk() {
+        // An empty zip archive
+        const BODY: &[u8] = b"PK\x05\x06\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0";
+        let dir = tempfile::tempdir().unwrap();
+        let mut options = BuildOptions::new(serve_without_content_length(BODY));
+        // The length announced to the HEAD request must not count
+        options.preflight = true;
+        let client = http_client(&options).unwrap();
+
+        let download = download_archive(&client, &options, dir.path()).unwrap();
+
+        assert!(download.contents.is_none());
+        assert_eq!(download.format, ArchiveFormat::Zip);
+        assert_eq!(fs::read(&download.path).unwrap(), BODY);
+    }
+}
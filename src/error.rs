@@ -0,0 +1,36 @@
+use thiserror::Error;
+
+/// Errors returned by [`build_from_url`](crate::build_from_url), one variant
+/// per stage of the build so that callers can tell failures apart. The
+/// wrapped error carries the details.
+#[derive(Debug, Error)]
+pub enum MonteurError {
+    /// Downloading the archive, its signature or cloning the repository failed,
+    /// or the signature didn't verify.
+    #[error("Failed to download the project source")]
+    Download(#[source] anyhow::Error),
+
+    /// The archive couldn't be extracted.
+    #[error("Failed to extract the project source")]
+    Extract(#[source] anyhow::Error),
+
+    /// The project has no build file monteur recognizes.
+    #[error("No build system detected")]
+    Detection(#[source] anyhow::Error),
+
+    /// The build tool couldn't be run.
+    #[error("Failed to build the project")]
+    Build(#[source] anyhow::Error),
+
+    /// No suitable artifact was found, or it couldn't be copied.
+    #[error("Failed to collect the build artifacts")]
+    Artifact(#[source] anyhow::Error),
+
+    /// Uploading an artifact to `--upload-url` failed.
+    #[error("Failed to upload the build artifacts")]
+    Upload(#[source] anyhow::Error),
+
+    /// Preparing the work directory or bookkeeping around the build failed.
+    #[error("I/O error")]
+    Io(#[source] anyhow::Error),
+}
@@ -0,0 +1,271 @@
+use anyhow::{Context, Result};
+use log::info;
+use nix::sys::statvfs::statvfs;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Moves a file or directory, falling back to copy-then-delete when renaming
+/// fails, which happens for read-only entries on some filesystems.
+pub(crate) fn move_path(source: &Path, target: &Path) -> Result<()> {
+    if let Err(err) = fs::rename(source, target) {
+        info!("Renaming {:?} failed ({}), copying it instead", source, err);
+        copy_path(source, target)?;
+        remove_path(source)?;
+    }
+    Ok(())
+}
+
+/// Recursively copies a file, symlink or directory, preserving permissions.
+fn copy_path(source: &Path, target: &Path) -> Result<()> {
+    let metadata = fs::symlink_metadata(source).context(format!("Failed to stat {:?}", source))?;
+
+    if metadata.is_symlink() {
+        let link = fs::read_link(source).context(format!("Failed to read link {:?}", source))?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&link, target)
+            .context(format!("Failed to create link {:?}", target))?;
+        #[cfg(not(unix))]
+        fs::copy(source, target).context(format!("Failed to copy {:?}", source))?;
+    } else if metadata.is_dir() {
+        fs::create_dir(target).context(format!("Failed to create directory {:?}", target))?;
+        for entry in fs::read_dir(source).context(format!("Failed to read {:?}", source))? {
+            let entry = entry.context(format!("Failed to read entry of {:?}", source))?;
+            copy_path(&entry.path(), &target.join(entry.file_name()))?;
+        }
+        // Restore the permissions only after the contents were written, as
+        // they may forbid writing into the directory
+        fs::set_permissions(target, metadata.permissions())
+            .context(format!("Failed to set permissions of {:?}", target))?;
+    } else {
+        // fs::copy also copies the permission bits
+        fs::copy(source, target).context(format!("Failed to copy {:?}", source))?;
+    }
+    Ok(())
+}
+
+/// Removes a file or directory, even if it or its contents are read-only.
+pub(crate) fn remove_path(path: &Path) -> Result<()> {
+    let metadata = fs::symlink_metadata(path).context(format!("Failed to stat {:?}", path))?;
+    if metadata.is_dir() {
+        for entry in WalkDir::new(path)
+            .into_iter()
+            .filter_entry(|e| e.file_type().is_dir())
+        {
+            let entry = entry.context(format!("Failed to walk {:?}", path))?;
+            make_writable(entry.path())?;
+        }
+        fs::remove_dir_all(path).context(format!("Failed to remove {:?}", path))?;
+    } else {
+        fs::remove_file(path).context(format!("Failed to remove {:?}", path))?;
+    }
+    Ok(())
+}
+
+/// Adds the execute permission to a file, e.g. a build tool wrapper script
+/// whose mode was lost when the project was archived.
+pub(crate) fn make_executable(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut permissions = fs::metadata(path)
+            .context(format!("Failed to stat {:?}", path))?
+            .permissions();
+        if permissions.mode() & 0o111 != 0o111 {
+            permissions.set_mode(permissions.mode() | 0o111);
+            fs::set_permissions(path, permissions)
+                .context(format!("Failed to make {:?} executable", path))?;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Adds the owner write permission to a file or directory, if missing.
+pub(crate) fn make_writable(path: &Path) -> Result<()> {
+    let mut permissions = fs::metadata(path)
+        .context(format!("Failed to stat {:?}", path))?
+        .permissions();
+    if permissions.readonly() {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            permissions.set_mode(permissions.mode() | 0o200);
+        }
+        #[cfg(not(unix))]
+        permissions.set_readonly(false);
+        fs::set_permissions(path, permissions)
+            .context(format!("Failed to make {:?} writable", path))?;
+    }
+    Ok(())
+}
+
+/// Fails with a clear message if the output directory is a dangling symlink,
+/// e.g. to a volume that wasn't mounted, instead of letting the copy fail
+/// obscurely later. Symlinks to existing directories are fine.
+pub(crate) fn check_output_dir(output_dir: &Path) -> Result<()> {
+    let Ok(metadata) = fs::symlink_metadata(output_dir) else {
+        // Doesn't exist yet and will be created
+        return Ok(());
+    };
+    if metadata.is_symlink() && !output_dir.exists() {
+        let target =
+            fs::read_link(output_dir).context(format!("Failed to read link {:?}", output_dir))?;
+        anyhow::bail!(
+            "Output directory {:?} is a symlink to {:?}, which doesn't exist. Is the volume mounted?",
+            output_dir,
+            target
+        );
+    }
+    if output_dir.exists() && !output_dir.is_dir() {
+        anyhow::bail!("Output path {:?} exists but is not a directory", output_dir);
+    }
+    Ok(())
+}
+
+/// Parses a path given with `--subdir`, which must stay inside the project.
+pub(crate) fn parse_relative_path(path: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(path);
+    if path.as_os_str().is_empty() || !is_contained(&path) {
+        return Err(format!(
+            "expected a relative path inside the project, got {:?}",
+            path
+        ));
+    }
+    Ok(path)
+}
+
+/// Returns whether the relative `path` stays inside the directory it is
+/// joined to, i.e. is neither absolute nor contains `..`.
+pub(crate) fn is_contained(path: &Path) -> bool {
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// Returns whether `name` can name a file in a directory: a single path
+/// component other than `.` and `..`, without separators.
+pub(crate) fn is_file_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    !name.contains(['/', '\\'])
+        && matches!(components.next(), Some(Component::Normal(_)))
+        && components.next().is_none()
+}
+
+/// Describes the entries of `dir` for error messages, directories with a
+/// trailing slash, listing at most `limit` of them.
+pub(crate) fn describe_dir(dir: &Path, limit: usize) -> String {
+    let mut entries: Vec<String> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                    name + "/"
+                } else {
+                    name
+                }
+            })
+            .collect(),
+        Err(err) => return format!("couldn't be listed ({})", err),
+    };
+    if entries.is_empty() {
+        return "is empty".to_string();
+    }
+    entries.sort();
+    let total = entries.len();
+    entries.truncate(limit);
+    let mut description = format!("contains {}", entries.join(", "));
+    if total > limit {
+        description.push_str(&format!(" and {} more", total - limit));
+    }
+    description
+}
+
+/// Disk space available to unprivileged users on the filesystem of `path`.
+pub(crate) fn available_space(path: &Path) -> Result<u64> {
+    let stat = statvfs(path).context(format!("Failed to query the filesystem of {:?}", path))?;
+    Ok(stat.blocks_available() as u64 * stat.fragment_size() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The fallback of [`move_path`] when renaming fails, e.g. across
+    /// filesystems.
+    #[cfg(unix)]
+    #[test]
+    fn copy_then_remove_moves_read_only_files() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("project");
+        fs::create_dir(&source).unwrap();
+        fs::write(source.join("pom.xml"), "<project/>").unwrap();
+        fs::set_permissions(source.join("pom.xml"), fs::Permissions::from_mode(0o444)).unwrap();
+        fs::set_permissions(&source, fs::Permissions::from_mode(0o555)).unwrap();
+
+        let target = dir.path().join("moved");
+        copy_path(&source, &target).unwrap();
+        remove_path(&source).unwrap();
+
+        let pom = target.join("pom.xml");
+        assert!(!source.exists());
+        assert_eq!(fs::read_to_string(&pom).unwrap(), "<project/>");
+        assert_eq!(
+            fs::metadata(&pom).unwrap().permissions().mode() & 0o777,
+            0o444
+        );
+        assert_eq!(
+            fs::metadata(&target).unwrap().permissions().mode() & 0o777,
+            0o555
+        );
+        // Let the temporary directory be cleaned up
+        make_writable(&target).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn check_output_dir_follows_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let existing_dir = dir.path().join("volume");
+        fs::create_dir(&existing_dir).unwrap();
+        let file = dir.path().join("file");
+        fs::write(&file, "").unwrap();
+
+        let dangling = dir.path().join("dangling");
+        symlink(dir.path().join("unmounted"), &dangling).unwrap();
+        let error = check_output_dir(&dangling).unwrap_err().to_string();
+        assert!(error.contains("doesn't exist"), "{}", error);
+
+        let to_file = dir.path().join("to-file");
+        symlink(&file, &to_file).unwrap();
+        let error = check_output_dir(&to_file).unwrap_err().to_string();
+        assert!(error.contains("not a directory"), "{}", error);
+
+        let to_dir = dir.path().join("to-dir");
+        symlink(&existing_dir, &to_dir).unwrap();
+        check_output_dir(&to_dir).unwrap();
+        check_output_dir(&dir.path().join("missing")).unwrap();
+    }
+
+    #[test]
+    fn is_file_name_accepts_single_components_only() {
+        assert!(is_file_name("app-1.0.jar"));
+        assert!(is_file_name("..jar"));
+        for name in [
+            "",
+            ".",
+            "..",
+            "../app.jar",
+            "lib/app.jar",
+            r"lib\app.jar",
+            "/app.jar",
+        ] {
+            assert!(!is_file_name(name), "{:?}", name);
+        }
+    }
+}
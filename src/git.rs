@@ -0,0 +1,55 @@
+use anyhow::{Context, Result};
+use log::info;
+use std::path::Path;
+use std::process::Command;
+
+/// Returns whether the URL points at a git remote rather than an archive.
+pub(crate) fn is_git_url(url: &str) -> bool {
+    url.starts_with("git@")
+        || url.starts_with("git://")
+        || url.starts_with("ssh://")
+        || url.trim_end_matches('/').ends_with(".git")
+}
+
+/// Validates a URL or `--git-ref` passed to git, which would take one starting
+/// with `-` for an option, such as `--upload-pack=<command>`.
+pub(crate) fn parse_git_arg(value: &str) -> Result<String, String> {
+    if value.starts_with('-') {
+        return Err(format!("must not start with '-', got '{}'", value));
+    }
+    Ok(value.to_string())
+}
+
+/// Shallow-clones the repository into `dest_path`, checking out `git_ref` if given.
+pub(crate) fn clone_repository(url: &str, git_ref: Option<&str>, dest_path: &Path) -> Result<()> {
+    info!("Cloning repository: {}", url);
+
+    let git = |args: &[&str]| -> Result<()> {
+        let status = Command::new("git")
+            .args(args)
+            .status()
+            .context("Failed to run git")?;
+        if !status.success() {
+            anyhow::bail!("git {} failed with {}", args.join(" "), status);
+        }
+        Ok(())
+    };
+
+    let dest = dest_path.to_string_lossy();
+    match git_ref {
+        // Fetching the ref directly works for branches, tags and commit hashes alike
+        Some(git_ref) => {
+            info!("Checking out ref: {}", git_ref);
+            git(&["init", "--quiet", "--", &dest])?;
+            git(&["-C", &dest, "remote", "add", "--", "origin", url])?;
+            git(&[
+                "-C", &dest, "fetch", "--quiet", "--depth", "1", "--", "origin", git_ref,
+            ])?;
+            git(&["-C", &dest, "checkout", "--quiet", "FETCH_HEAD"])?;
+        }
+        None => git(&["clone", "--quiet", "--depth", "1", "--", url, &dest])?,
+    }
+
+    info!("Repository successfully cloned");
+    Ok(())
+}
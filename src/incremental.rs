@@ -0,0 +1,292 @@
+use crate::{BuildOptions, BuildSystem};
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use glob::Pattern;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::fs::{self, File};
+use std::io::{Read, copy};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use walkdir::WalkDir;
+
+/// File next to the temp directory that records the last successful build
+/// for `--incremental` and `--skip-build-if-fresh`.
+pub(crate) const INCREMENTAL_STATE_FILE: &str = ".monteur-incremental.json";
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct IncrementalState {
+    pub(crate) archive_sha256: String,
+    pub(crate) build_fingerprint: String,
+    pub(crate) build_system: Option<BuildSystem>,
+    pub(crate) artifacts: Vec<PathBuf>,
+}
+
+/// The options that affect what gets built and delivered, in the form they
+/// are fingerprinted in. Download, verification and reporting options are left
+/// out, so that changing them doesn't force a rebuild.
+#[derive(Serialize)]
+struct BuildFingerprint<'a> {
+    archive_format: Option<String>,
+    extract_only: Vec<&'a str>,
+    no_flatten: bool,
+    pick_latest_dir: bool,
+    subdir: Option<Cow<'a, str>>,
+    unwrap_nested: bool,
+    artifact_kind: Option<String>,
+    artifact_type: Option<String>,
+    artifact_type_priority: Vec<Option<String>>,
+    all_artifacts: bool,
+    preserve_module_paths: bool,
+    artifact_search_depth: usize,
+    artifact_paths: &'a [String],
+    artifact_glob: Option<&'a str>,
+    min_artifact_size: Option<u64>,
+    exclude_prefixes: &'a [String],
+    no_shaded: bool,
+    require_all: bool,
+    custom_build: Option<&'a str>,
+    custom_artifact_path: Option<&'a str>,
+    pants_goal: &'a str,
+    pants_targets: &'a [String],
+    buck_target: Option<&'a str>,
+    offline: bool,
+    fail_on_build_warning: bool,
+    build_warning_patterns: &'a [String],
+    retry_on_empty_artifact: bool,
+    gradle_cache: Option<Cow<'a, str>>,
+    gradle_version: Option<&'a str>,
+    skip_mode: Option<String>,
+    maven_module: Option<&'a str>,
+    maven_command: &'a str,
+    maven_defines: &'a [(String, String)],
+    maven_local_repo: Option<Cow<'a, str>>,
+    use_bundled_maven_config: bool,
+    gradle_properties: &'a [(String, String)],
+    gradle_tasks: &'a [String],
+    threads: Option<u32>,
+    memory: Option<&'a str>,
+    project_jdk: bool,
+    jdk_dirs: Vec<Cow<'a, str>>,
+    java_home: Option<Cow<'a, str>>,
+    no_daemon: bool,
+    warmup: bool,
+    output_name_template: Option<&'a str>,
+    output_format: Option<String>,
+    include_pom: bool,
+    work_dir: Cow<'a, str>,
+    output_dir: Cow<'a, str>,
+}
+
+/// Fingerprints every option that affects the build, so that `--incremental`
+/// rebuilds whenever one of them changes.
+pub(crate) fn build_fingerprint(options: &BuildOptions) -> String {
+    let fingerprint = BuildFingerprint {
+        archive_format: options.archive_format.as_ref().and_then(value_name),
+        extract_only: options.extract_only.iter().map(Pattern::as_str).collect(),
+        no_flatten: options.no_flatten,
+        pick_latest_dir: options.pick_latest_dir,
+        subdir: options.subdir.as_deref().map(Path::to_string_lossy),
+        unwrap_nested: options.unwrap_nested,
+        artifact_kind: value_name(&options.artifact_kind),
+        artifact_type: value_name(&options.artifact_type),
+        artifact_type_priority: options
+            .artifact_type_priority
+            .iter()
+            .map(value_name)
+            .collect(),
+        all_artifacts: options.all_artifacts,
+        preserve_module_paths: options.preserve_module_paths,
+        artifact_search_depth: options.artifact_search_depth,
+        artifact_paths: &options.artifact_paths,
+        artifact_glob: options.artifact_glob.as_ref().map(Pattern::as_str),
+        min_artifact_size: options.min_artifact_size,
+        exclude_prefixes: &options.exclude_prefixes,
+        no_shaded: options.no_shaded,
+        require_all: options.require_all,
+        custom_build: options.custom_build.as_deref(),
+        custom_artifact_path: options.custom_artifact_path.as_deref(),
+        pants_goal: &options.pants_goal,
+        pants_targets: &options.pants_targets,
+        buck_target: options.buck_target.as_deref(),
+        offline: options.offline,
+        fail_on_build_warning: options.fail_on_build_warning,
+        build_warning_patterns: &options.build_warning_patterns,
+        retry_on_empty_artifact: options.retry_on_empty_artifact,
+        gradle_cache: options.gradle_cache.as_deref().map(Path::to_string_lossy),
+        gradle_version: options.gradle_version.as_deref(),
+        skip_mode: value_name(&options.skip_mode),
+        maven_module: options.maven_module.as_deref(),
+        maven_command: &options.maven_command,
+        maven_defines: &options.maven_defines,
+        maven_local_repo: options
+            .maven_local_repo
+            .as_deref()
+            .map(Path::to_string_lossy),
+        use_bundled_maven_config: options.use_bundled_maven_config,
+        gradle_properties: &options.gradle_properties,
+        gradle_tasks: &options.gradle_tasks,
+        threads: options.threads,
+        memory: options.memory.as_deref(),
+        project_jdk: options.project_jdk,
+        jdk_dirs: options
+            .jdk_dirs
+            .iter()
+            .map(|dir| dir.to_string_lossy())
+            .collect(),
+        java_home: options.java_home.as_deref().map(Path::to_string_lossy),
+        no_daemon: options.no_daemon,
+        warmup: options.warmup,
+        output_name_template: options.output_name_template.as_deref(),
+        output_format: value_name(&options.output_format),
+        include_pom: options.include_pom,
+        work_dir: options.work_dir.to_string_lossy(),
+        output_dir: options.output_dir.to_string_lossy(),
+    };
+    let json = serde_json::to_vec(&fingerprint).expect("options serialize to JSON");
+    sha256(json.as_slice()).expect("reading from a slice can't fail")
+}
+
+/// Returns the command-line name of an option value, which unlike its `Debug`
+/// form is kept stable.
+fn value_name(value: &impl ValueEnum) -> Option<String> {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+}
+
+/// Reads the state of the last `--incremental` build, if there is one.
+pub(crate) fn load_incremental_state(path: &Path) -> Result<Option<IncrementalState>> {
+    match fs::read(path) {
+        Ok(contents) => match serde_json::from_slice(&contents) {
+            Ok(state) => Ok(Some(state)),
+            Err(err) => {
+                warn!("Ignoring unreadable incremental state {:?}: {}", path, err);
+                Ok(None)
+            }
+        },
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).context("Failed to read incremental state"),
+    }
+}
+
+/// Records the state of a successful build.
+pub(crate) fn save_incremental_state(path: &Path, state: &IncrementalState) -> Result<()> {
+    let contents = serde_json::to_vec_pretty(state)?;
+    fs::write(path, contents).context("Failed to write incremental state")
+}
+
+/// For `--skip-build-if-fresh`, returns the artifacts of the last build if it
+/// used the same options and build system, they all still exist, and no file
+/// of the project at `project_root` was modified after the oldest of them.
+/// Extraction keeps the modification times recorded in the archive.
+pub(crate) fn fresh_artifacts(
+    state_path: &Path,
+    state: &IncrementalState,
+    build_system: BuildSystem,
+    project_root: &Path,
+) -> Result<Option<Vec<PathBuf>>> {
+    let Some(previous) = load_incremental_state(state_path)? else {
+        return Ok(None);
+    };
+    if previous.build_fingerprint != state.build_fingerprint
+        || previous.build_system != Some(build_system)
+        || previous.artifacts.is_empty()
+    {
+        debug!("The last build used other options, building anyway");
+        return Ok(None);
+    }
+
+    let mut built = SystemTime::now();
+    for path in &previous.artifacts {
+        match fs::metadata(path).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => built = built.min(modified),
+            Err(_) => {
+                debug!(
+                    "{} of the last build is gone, building anyway",
+                    path.display()
+                );
+                return Ok(None);
+            }
+        }
+    }
+    for entry in WalkDir::new(project_root) {
+        let entry = entry.context(format!("Failed to walk {:?}", project_root))?;
+        let modified = entry
+            .metadata()
+            .context(format!("Failed to stat {:?}", entry.path()))?
+            .modified()
+            .context(format!(
+                "Failed to read the modification time of {:?}",
+                entry.path()
+            ))?;
+        if entry.file_type().is_file() && modified > built {
+            debug!(
+                "{} is newer than the artifacts of the last build",
+                entry.path().display()
+            );
+            return Ok(None);
+        }
+    }
+    Ok(Some(previous.artifacts))
+}
+
+/// Returns the hex-encoded SHA-256 digest of a file's contents.
+pub(crate) fn sha256_file(path: &Path) -> Result<String> {
+    let file = File::open(path).context(format!("Failed to open {:?}", path))?;
+    sha256(file).context(format!("Failed to hash {:?}", path))
+}
+
+/// Returns the hex-encoded SHA-256 digest of everything `reader` yields.
+pub(crate) fn sha256(mut reader: impl Read) -> Result<String> {
+    let mut hasher = Sha256::new();
+    copy(&mut reader, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        options: BuildOptions,
+    }
+
+    fn fingerprint(args: &[&str]) -> String {
+        let args = ["monteur", "https://example.com/app.zip"]
+            .iter()
+            .chain(args);
+        build_fingerprint(&Cli::parse_from(args).options)
+    }
+
+    #[test]
+    fn build_fingerprint_changes_with_build_options_only() {
+        let default = fingerprint(&[]);
+        assert_eq!(fingerprint(&[]), default);
+        assert_eq!(fingerprint(&["--retries", "5", "--insecure"]), default);
+
+        for args in [
+            &["--threads", "4"][..],
+            &["--memory", "2g"],
+            &["--offline"],
+            &["--skip-mode", "run"],
+            &["--artifact-glob", "*-all.jar"],
+            &["--output-dir", "dist"],
+        ] {
+            assert_ne!(fingerprint(args), default, "{:?}", args);
+        }
+        assert_eq!(
+            fingerprint(&["--threads", "4"]),
+            fingerprint(&["--threads", "4"])
+        );
+    }
+}
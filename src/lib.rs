@@ -0,0 +1,1339 @@
+//! Download, build and collect the artifact of a Java project.
+//!
+//! The `monteur` binary is a thin command line wrapper around
+//! [`build_from_url`], which can also be called directly to embed monteur in
+//! other tools.
+
+mod archive;
+mod artifact;
+mod build_system;
+mod download;
+mod error;
+mod fs_util;
+mod git;
+mod incremental;
+mod project;
+mod project_config;
+mod toolchain;
+
+pub use archive::ArchiveFormat;
+pub use artifact::{ArtifactFileType, ArtifactKind, ArtifactType, OutputFormat};
+pub use build_system::{BuildSystem, SkipMode, terminate_build};
+pub use error::MonteurError;
+
+use anyhow::{Context, anyhow};
+use clap::Args;
+use glob::Pattern;
+use incremental::{INCREMENTAL_STATE_FILE, IncrementalState};
+use log::{info, warn};
+use reqwest::blocking::Client;
+use reqwest::header::{HeaderName, HeaderValue};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Default `User-Agent` header sent with downloads.
+pub const DEFAULT_USER_AGENT: &str = concat!("monteur/", env!("CARGO_PKG_VERSION"));
+
+/// Number of project root entries listed when no build system is detected.
+const DESCRIBED_ENTRIES: usize = 20;
+
+/// Options controlling how a project is downloaded, built and collected.
+#[derive(Args, Clone, Debug)]
+pub struct BuildOptions {
+    /// URL of the archive or git repository containing the project source
+    #[arg(
+        value_name = "DOWNLOAD_URL",
+        required_unless_present = "coordinate",
+        default_value = "",
+        hide_default_value = true,
+        value_parser = git::parse_git_arg
+    )]
+    pub url: String,
+
+    /// Maven coordinate GROUP:ARTIFACT:VERSION[:CLASSIFIER[:EXTENSION]] of the
+    /// source artifact to download from --repository instead of a URL; the
+    /// classifier defaults to sources and the extension to jar
+    #[arg(
+        long,
+        value_name = "COORDINATE",
+        conflicts_with_all = ["url", "git_ref"],
+        value_parser = download::parse_coordinate
+    )]
+    pub coordinate: Option<String>,
+
+    /// Base URL of the Maven repository --coordinate is resolved against
+    #[arg(long, value_name = "URL", default_value = download::MAVEN_CENTRAL)]
+    pub repository: String,
+
+    /// Git ref (branch, tag or commit) to build; treats the URL as a git remote
+    #[arg(long, value_parser = git::parse_git_arg)]
+    pub git_ref: Option<String>,
+
+    /// User-Agent header sent when downloading the archive
+    #[arg(long, default_value = DEFAULT_USER_AGENT)]
+    pub user_agent: String,
+
+    /// Abort the download if no data arrives for this many seconds
+    #[arg(long, value_name = "SECONDS")]
+    pub stall_timeout: Option<u64>,
+
+    /// Extra HTTP header sent with every download, as `Name: Value` (repeatable)
+    #[arg(long = "header", value_name = "NAME: VALUE", value_parser = download::parse_header)]
+    pub headers: Vec<(HeaderName, HeaderValue)>,
+
+    /// File with extra HTTP headers, one `Name: Value` per line
+    #[arg(long, value_name = "PATH")]
+    pub headers_file: Option<PathBuf>,
+
+    /// Accept invalid TLS certificates, e.g. self-signed ones; prefer --cacert
+    #[arg(long)]
+    pub insecure: bool,
+
+    /// Additional CA certificate(s) in PEM format to trust for downloads
+    #[arg(long, value_name = "PATH")]
+    pub cacert: Option<PathBuf>,
+
+    /// How often to retry a download that was rate limited (HTTP 429)
+    #[arg(long, default_value_t = 3)]
+    pub retries: u32,
+
+    /// Check the URL with a HEAD request before downloading, failing early on
+    /// an error status and logging the size and type of the download
+    #[arg(long)]
+    pub preflight: bool,
+
+    /// How many HTTP redirects to follow before giving up
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    pub max_redirects: usize,
+
+    /// URL of a detached OpenPGP signature (.asc/.sig) to verify the archive with
+    #[arg(long, requires = "pubkey")]
+    pub signature_url: Option<String>,
+
+    /// Public key (ASCII-armored or binary) the archive signature must verify against
+    #[arg(long, requires = "signature_url")]
+    pub pubkey: Option<PathBuf>,
+
+    /// Archive format to use instead of detecting it from the URL and the file contents
+    #[arg(long, value_enum)]
+    pub archive_format: Option<ArchiveFormat>,
+
+    /// Keep downloaded archives of up to this many bytes (as announced by the
+    /// server) in memory instead of saving them before extraction; 0 disables it
+    #[arg(long, value_name = "BYTES", default_value_t = 8 * 1024 * 1024)]
+    pub in_memory_limit: u64,
+
+    /// Only extract archive entries matching this glob (repeatable); build files
+    /// are always extracted
+    #[arg(long, value_name = "GLOB", value_parser = Pattern::new)]
+    pub extract_only: Vec<Pattern>,
+
+    /// Leave the extracted tree as it is instead of moving the contents of a
+    /// single top-level folder up or picking the folder with build files
+    #[arg(long, conflicts_with = "unwrap_nested")]
+    pub no_flatten: bool,
+
+    /// When several top-level folders with build files carry versions in
+    /// their names (`app-1.0/`, `app-1.1/`), build the one with the highest
+    /// version
+    #[arg(long, conflicts_with = "no_flatten")]
+    pub pick_latest_dir: bool,
+
+    /// Directory, relative to the extracted project, to use as the build root
+    #[arg(long, value_name = "DIR", value_parser = fs_util::parse_relative_path)]
+    pub subdir: Option<PathBuf>,
+
+    /// Kind of artifact to deliver; `distribution` copies the archives of
+    /// Gradle's `application` plugin from build/distributions/
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        conflicts_with_all = ["all_artifacts", "interactive"]
+    )]
+    pub artifact_kind: ArtifactKind,
+
+    /// Type of the artifact to select and copy: JAR or WAR files, or `auto`
+    /// for both, preferring the type that comes first in
+    /// --artifact-type-priority
+    #[arg(long, value_enum, default_value_t, conflicts_with = "artifact_kind")]
+    pub artifact_type: ArtifactType,
+
+    /// Order in which `--artifact-type auto` prefers the types of the built
+    /// artifacts, comma-separated
+    #[arg(
+        long,
+        value_enum,
+        value_name = "TYPES",
+        value_delimiter = ',',
+        default_values_t = [ArtifactFileType::War, ArtifactFileType::Jar]
+    )]
+    pub artifact_type_priority: Vec<ArtifactFileType>,
+
+    /// Also extract an archive found inside the archive if the project has no
+    /// build files otherwise, up to a few levels deep
+    #[arg(long, conflicts_with = "extract_only")]
+    pub unwrap_nested: bool,
+
+    /// Copy every JAR produced by the build instead of selecting a single one
+    #[arg(long)]
+    pub all_artifacts: bool,
+
+    /// Ask which JAR to copy when several were built and a terminal is attached,
+    /// unless --artifact-glob selects among them
+    #[arg(long, conflicts_with = "all_artifacts")]
+    pub interactive: bool,
+
+    /// Mirror each JAR under <output>/<module>/ based on the module it was built in
+    #[arg(long, requires = "all_artifacts")]
+    pub preserve_module_paths: bool,
+
+    /// Maximum directory depth below the project root searched for module JARs
+    /// with --all-artifacts, or for the JARs matching --artifact-glob
+    /// (`core/target/app.jar` has depth 3)
+    #[arg(long, value_name = "N", default_value_t = 4)]
+    pub artifact_search_depth: usize,
+
+    /// Additional directory, relative to the project root, to search for the
+    /// JAR next to the build system's own (repeatable), e.g. `out/` or `dist/`
+    #[arg(
+        long = "artifact-path",
+        visible_alias = "repo-layout",
+        value_name = "DIR",
+        conflicts_with_all = ["all_artifacts", "artifact_kind"]
+    )]
+    pub artifact_paths: Vec<String>,
+
+    /// Glob, relative to the project root, matching the JARs to select the
+    /// artifact from instead of searching the artifact directories, e.g.
+    /// `build/libs/*-all.jar`
+    #[arg(
+        long,
+        value_name = "GLOB",
+        value_parser = Pattern::new,
+        conflicts_with_all = ["all_artifacts", "artifact_kind", "artifact_paths"]
+    )]
+    pub artifact_glob: Option<Pattern>,
+
+    /// Ignore JARs smaller than this many bytes when selecting the artifact,
+    /// e.g. thin JARs and plugin stubs next to the fat JAR
+    #[arg(long, value_name = "BYTES", conflicts_with_all = ["all_artifacts", "artifact_kind"])]
+    pub min_artifact_size: Option<u64>,
+
+    /// Shell command that builds the project, run in the project root instead
+    /// of a detected build system
+    #[arg(long, value_name = "COMMAND", requires = "custom_artifact_path")]
+    pub custom_build: Option<String>,
+
+    /// Directory, relative to the project root, --custom-build writes its JARs to
+    #[arg(long, value_name = "DIR", requires = "custom_build")]
+    pub custom_artifact_path: Option<String>,
+
+    /// Pants goal that builds the artifacts
+    #[arg(long, value_name = "GOAL", default_value = "package")]
+    pub pants_goal: String,
+
+    /// Pants target address to build, e.g. `src/java/app:bin` (repeatable)
+    #[arg(long = "pants-target", value_name = "ADDRESS", default_value = "::")]
+    pub pants_targets: Vec<String>,
+
+    /// Buck2 target to build, e.g. `//app:app`; required for Buck2 projects
+    #[arg(long, value_name = "TARGET")]
+    pub buck_target: Option<String>,
+
+    /// Also upload the artifacts with an HTTP PUT to this URL, sending the
+    /// download headers; a URL ending in `/` gets the file name appended
+    #[arg(long, value_name = "URL")]
+    pub upload_url: Option<String>,
+
+    /// File name prefix of intermediate Maven JARs that are only selected if
+    /// nothing else was built (repeatable)
+    #[arg(
+        long = "exclude-prefix",
+        value_name = "PREFIX",
+        default_value = "original-"
+    )]
+    pub exclude_prefixes: Vec<String>,
+
+    /// Select the plain Maven JAR without a classifier instead of preferring
+    /// the shaded one, e.g. when the runtime provides the dependencies
+    #[arg(long, conflicts_with = "all_artifacts")]
+    pub no_shaded: bool,
+
+    /// With --all-artifacts, fail if any JAR can't be copied instead of
+    /// delivering the others
+    #[arg(long, requires = "all_artifacts")]
+    pub require_all: bool,
+
+    /// Name the copied JAR after this template, e.g. "{name}-{version}.jar",
+    /// with the project name and version read from the build files
+    #[arg(long, value_name = "TEMPLATE", conflicts_with_all = ["all_artifacts", "artifact_kind"])]
+    pub output_name_template: Option<String>,
+
+    /// Deliver the JAR as it is, or packaged together with its companion files
+    /// (see --include-pom) in a zip or tar.gz with a `.sha256` checksum file
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = OutputFormat::Jar,
+        conflicts_with_all = ["all_artifacts", "artifact_kind"]
+    )]
+    pub output_format: OutputFormat,
+
+    /// Also copy each Maven JAR's POM (a generated `.pom` in target/ or the
+    /// module's pom.xml) to the output directory, named after the JAR
+    #[arg(long)]
+    pub include_pom: bool,
+
+    /// Fail instead of replacing an artifact that already exists in the output
+    /// directory
+    #[arg(long)]
+    pub no_overwrite: bool,
+
+    /// Run Maven (`-o`) and Gradle (`--offline`) without network access, using
+    /// only dependencies already in the local repository
+    #[arg(short = 'o', long)]
+    pub offline: bool,
+
+    /// Fail if the build output contains warnings, such as `[WARNING]` lines
+    /// of Maven or Gradle's deprecation notice
+    #[arg(long)]
+    pub fail_on_build_warning: bool,
+
+    /// When the build fails, also because of --fail-on-build-warning or because
+    /// the build tool can't be started, write why and the build output to
+    /// monteur-build-failure.log in the output directory, for CI to collect
+    #[arg(long)]
+    pub error_output: bool,
+
+    /// When the build succeeds but no JAR file is found, rebuild once from
+    /// scratch, with Gradle without its daemon, before failing
+    #[arg(long)]
+    pub retry_on_empty_artifact: bool,
+
+    /// Text marking a warning in the build output (repeatable); replaces the
+    /// build system's defaults
+    #[arg(
+        long = "build-warning-pattern",
+        value_name = "TEXT",
+        requires = "fail_on_build_warning"
+    )]
+    pub build_warning_patterns: Vec<String>,
+
+    /// Prepopulated Gradle user home (dependency cache) to build with, set as
+    /// GRADLE_USER_HOME; combine with --offline to build without network access
+    #[arg(long, value_name = "DIR")]
+    pub gradle_cache: Option<PathBuf>,
+
+    /// Gradle version to build with, written to the distributionUrl of the
+    /// project's gradle/wrapper/gradle-wrapper.properties before the build
+    #[arg(long, value_name = "VERSION", value_parser = build_system::parse_gradle_version)]
+    pub gradle_version: Option<String>,
+
+    /// Whether tests are skipped entirely, compiled but not run, or run
+    #[arg(long, value_enum, default_value_t)]
+    pub skip_mode: SkipMode,
+
+    /// Only build the Maven module with this artifactId and the modules it
+    /// depends on (`-pl :<ARTIFACT_ID> -am`), and take the artifact from its target/
+    #[arg(long, value_name = "ARTIFACT_ID")]
+    pub maven_module: Option<String>,
+
+    /// Arguments of the Maven build, split on whitespace; the placeholders
+    /// {skip}, {offline}, {module}, {defines}, {config} and {threads} are
+    /// replaced with the arguments of the corresponding options; {defines}
+    /// also holds --maven-local-repo
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        default_value = build_system::DEFAULT_MAVEN_COMMAND,
+        value_parser = build_system::parse_maven_command
+    )]
+    pub maven_command: String,
+
+    /// System property passed to Maven as `-DKEY=VALUE` (repeatable)
+    #[arg(
+        long = "maven-define",
+        value_name = "KEY=VALUE",
+        value_parser = build_system::parse_property
+    )]
+    pub maven_defines: Vec<(String, String)>,
+
+    /// Local Maven repository to build with, passed as
+    /// `-Dmaven.repo.local=<DIR>`, to keep concurrent builds from sharing one
+    #[arg(long, value_name = "DIR")]
+    pub maven_local_repo: Option<PathBuf>,
+
+    /// Build with the settings.xml and toolchains.xml the project ships in
+    /// .mvn/ or ci/, passed to Maven with -s and -t
+    #[arg(long)]
+    pub use_bundled_maven_config: bool,
+
+    /// Project property passed to Gradle as `-PKEY=VALUE` (repeatable)
+    #[arg(
+        long = "gradle-property",
+        value_name = "KEY=VALUE",
+        value_parser = build_system::parse_property
+    )]
+    pub gradle_properties: Vec<(String, String)>,
+
+    /// Gradle tasks to run instead of `clean build`; set from the goals in
+    /// the project's .monteur.toml
+    #[arg(skip)]
+    pub gradle_tasks: Vec<String>,
+
+    /// Limit the build to N threads, passed as `-T N` to Maven and
+    /// `--max-workers=N` to Gradle
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub threads: Option<u32>,
+
+    /// Maximum JVM heap of the build, e.g. 2g, set as -Xmx in MAVEN_OPTS, or in
+    /// GRADLE_OPTS and org.gradle.jvmargs
+    #[arg(long, value_name = "SIZE", value_parser = build_system::parse_memory)]
+    pub memory: Option<String>,
+
+    /// Build with the JDK the project pins in .sdkmanrc or .tool-versions,
+    /// looked up in the --jdk-dirs, SDKMAN_DIR and the asdf installs
+    #[arg(long)]
+    pub project_jdk: bool,
+
+    /// Directory with one installed JDK per subdirectory, named by version,
+    /// to look up the project's JDK in (repeatable)
+    #[arg(long = "jdk-dir", value_name = "DIR", requires = "project_jdk")]
+    pub jdk_dirs: Vec<PathBuf>,
+
+    /// JDK the build runs with, set as JAVA_HOME; found with --project-jdk
+    #[arg(skip)]
+    pub java_home: Option<PathBuf>,
+
+    /// Run Gradle with `--no-daemon`; set for the rebuild of
+    /// --retry-on-empty-artifact
+    #[arg(skip)]
+    pub no_daemon: bool,
+
+    /// Print the environment the build process receives before starting it,
+    /// with the values of variables named like secrets redacted
+    #[arg(long)]
+    pub dump_env: bool,
+
+    /// Skip extraction and build when the archive and options are unchanged
+    /// since the last run and its artifacts still exist
+    #[arg(long)]
+    pub incremental: bool,
+
+    /// Skip the build when no file of the extracted project is newer than the
+    /// artifacts of the last build, which are delivered again; the build
+    /// options must be unchanged
+    #[arg(long)]
+    pub skip_build_if_fresh: bool,
+
+    /// Build the project and report the JARs considered for delivery, and
+    /// which one would be selected, without copying anything
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "all_artifacts",
+            "artifact_kind",
+            "interactive",
+            "incremental",
+            "skip_build_if_fresh",
+            "upload_url",
+        ]
+    )]
+    pub inspect: bool,
+
+    /// Download and extract the project and only detect its build system,
+    /// without building it
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "all_artifacts",
+            "artifact_kind",
+            "interactive",
+            "incremental",
+            "skip_build_if_fresh",
+            "upload_url",
+            "inspect",
+            "custom_build",
+        ]
+    )]
+    pub only_detect: bool,
+
+    /// Only download the project's dependencies into the local Maven
+    /// repository or Gradle user home (`mvn dependency:go-offline`, `gradlew
+    /// dependencies`), without building, to seed a cache for a later build
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "all_artifacts",
+            "artifact_kind",
+            "interactive",
+            "incremental",
+            "skip_build_if_fresh",
+            "upload_url",
+            "inspect",
+            "custom_build",
+            "only_detect",
+            "offline",
+        ]
+    )]
+    pub warmup: bool,
+
+    /// Directory the project is downloaded to and built in; wiped on every run
+    #[arg(skip = PathBuf::from("temp"))]
+    pub work_dir: PathBuf,
+
+    /// Directory the selected artifacts are copied to; a relative path is
+    /// relative to the directory monteur is started in
+    #[arg(long, value_name = "DIR", default_value = "/output")]
+    pub output_dir: PathBuf,
+}
+
+impl BuildOptions {
+    /// Creates options for building the project at `url` with the same
+    /// defaults as the command line.
+    pub fn new(url: impl Into<String>) -> Self {
+        BuildOptions {
+            url: url.into(),
+            coordinate: None,
+            repository: download::MAVEN_CENTRAL.to_string(),
+            git_ref: None,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            stall_timeout: None,
+            headers: Vec::new(),
+            headers_file: None,
+            insecure: false,
+            cacert: None,
+            retries: 3,
+            preflight: false,
+            max_redirects: 10,
+            signature_url: None,
+            pubkey: None,
+            archive_format: None,
+            in_memory_limit: 8 * 1024 * 1024,
+            extract_only: Vec::new(),
+            unwrap_nested: false,
+            no_flatten: false,
+            pick_latest_dir: false,
+            subdir: None,
+            artifact_kind: ArtifactKind::Jar,
+            artifact_type: ArtifactType::Jar,
+            artifact_type_priority: vec![ArtifactFileType::War, ArtifactFileType::Jar],
+            all_artifacts: false,
+            interactive: false,
+            preserve_module_paths: false,
+            artifact_search_depth: 4,
+            artifact_paths: Vec::new(),
+            artifact_glob: None,
+            min_artifact_size: None,
+            custom_build: None,
+            custom_artifact_path: None,
+            pants_goal: "package".to_string(),
+            pants_targets: vec!["::".to_string()],
+            buck_target: None,
+            exclude_prefixes: vec!["original-".to_string()],
+            no_shaded: false,
+            require_all: false,
+            output_name_template: None,
+            output_format: OutputFormat::Jar,
+            include_pom: false,
+            upload_url: None,
+            no_overwrite: false,
+            offline: false,
+            gradle_cache: None,
+            gradle_version: None,
+            fail_on_build_warning: false,
+            error_output: false,
+            retry_on_empty_artifact: false,
+            build_warning_patterns: Vec::new(),
+            skip_mode: SkipMode::Skip,
+            maven_module: None,
+            maven_command: build_system::DEFAULT_MAVEN_COMMAND.to_string(),
+            maven_defines: Vec::new(),
+            maven_local_repo: None,
+            use_bundled_maven_config: false,
+            gradle_properties: Vec::new(),
+            gradle_tasks: Vec::new(),
+            threads: None,
+            memory: None,
+            project_jdk: false,
+            jdk_dirs: Vec::new(),
+            java_home: None,
+            no_daemon: false,
+            dump_env: false,
+            incremental: false,
+            skip_build_if_fresh: false,
+            inspect: false,
+            only_detect: false,
+            warmup: false,
+            work_dir: PathBuf::from("temp"),
+            output_dir: PathBuf::from("/output"),
+        }
+    }
+}
+
+/// Result of a successful [`build_from_url`] run.
+#[derive(Clone, Debug)]
+pub struct BuildOutcome {
+    pub build_system: BuildSystem,
+    /// Artifacts copied to the output directory
+    pub artifacts: Vec<Artifact>,
+    /// Artifacts that were built but couldn't be delivered; only
+    /// `all_artifacts` tolerates these
+    pub failed: Vec<FailedArtifact>,
+    /// Whether the build was skipped because of `incremental` or
+    /// `skip_build_if_fresh`
+    pub reused: bool,
+    pub duration: Duration,
+    /// With `inspect`, what the selection was based on; nothing is copied then
+    pub inspection: Option<Inspection>,
+}
+
+/// The outcome of an `inspect` run.
+#[derive(Clone, Debug)]
+pub struct Inspection {
+    /// Where the project was built
+    pub project_root: PathBuf,
+    pub candidates: Vec<CandidateArtifact>,
+}
+
+/// A JAR considered for delivery.
+#[derive(Clone, Debug)]
+pub struct CandidateArtifact {
+    /// Where the build produced it
+    pub path: PathBuf,
+    pub size: u64,
+    /// Whether it is the JAR that gets delivered
+    pub selected: bool,
+    /// Why it was never considered, e.g. because it is a source JAR
+    pub excluded_reason: Option<String>,
+}
+
+/// An artifact copied to the output directory.
+#[derive(Clone, Debug)]
+pub struct Artifact {
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// An artifact that was built but couldn't be copied to the output directory.
+#[derive(Clone, Debug)]
+pub struct FailedArtifact {
+    /// Where the build produced it
+    pub path: PathBuf,
+    pub error: String,
+}
+
+/// Downloads the project, builds it and copies the selected artifacts to the
+/// output directory.
+pub fn build_from_url(options: &BuildOptions) -> Result<BuildOutcome, MonteurError> {
+    let started = Instant::now();
+    let options = &resolved_options(options)?;
+    let download_url = &options.url;
+    let client = start_run(options)?;
+    let temp_dir_path = options.work_dir.as_path();
+
+    let mut incremental = None;
+    let project_root = if options.git_ref.is_some() || git::is_git_url(download_url) {
+        if options.incremental {
+            warn!("--incremental only applies to archive downloads, building anyway");
+        }
+        if options.skip_build_if_fresh {
+            warn!("--skip-build-if-fresh only applies to archive downloads, building anyway");
+        }
+        git::clone_repository(download_url, options.git_ref.as_deref(), temp_dir_path)
+            .map_err(MonteurError::Download)?;
+        temp_dir_path.to_path_buf()
+    } else {
+        let download = download::download_archive(&client, options, temp_dir_path)
+            .map_err(MonteurError::Download)?;
+        let archive = download.source();
+        let format = download.format;
+
+        if let (Some(signature_url), Some(pubkey)) = (&options.signature_url, &options.pubkey) {
+            download::verify_signature(&client, archive, signature_url, pubkey)
+                .map_err(MonteurError::Download)?;
+        }
+
+        // --skip-build-if-fresh compares with the artifacts recorded in the
+        // same state, once the project is extracted
+        if options.incremental || options.skip_build_if_fresh {
+            let state_path = std::path::absolute(INCREMENTAL_STATE_FILE)
+                .context("Failed to resolve incremental state path")
+                .map_err(MonteurError::Io)?;
+            let state = IncrementalState {
+                archive_sha256: archive
+                    .open()
+                    .and_then(incremental::sha256)
+                    .context("Failed to hash the archive")
+                    .map_err(MonteurError::Io)?,
+                build_fingerprint: incremental::build_fingerprint(options),
+                build_system: None,
+                artifacts: Vec::new(),
+            };
+
+            let previous = incremental::load_incremental_state(&state_path)
+                .map_err(MonteurError::Io)?
+                .filter(|previous| {
+                    options.incremental
+                        && previous.archive_sha256 == state.archive_sha256
+                        && previous.build_fingerprint == state.build_fingerprint
+                        && previous.artifacts.iter().all(|path| path.exists())
+                });
+            if let Some(IncrementalState {
+                build_system: Some(build_system),
+                artifacts,
+                ..
+            }) = previous
+            {
+                info!("Archive and options unchanged since the last build, skipping it");
+                upload_artifacts(&client, options, &artifacts)?;
+                return outcome(build_system, artifacts, true, started);
+            }
+            incremental = Some((state_path, state));
+        }
+
+        archive::check_disk_space(archive, temp_dir_path).map_err(MonteurError::Extract)?;
+
+        // A .gz download may be a single compressed file such as a JAR rather
+        // than a tarball, which is delivered as it is
+        if format == ArchiveFormat::TarGz
+            && !archive::is_gzipped_tar(archive).map_err(MonteurError::Extract)?
+        {
+            let file =
+                archive::gunzip_single_file(archive, download.file_name.as_deref(), temp_dir_path)
+                    .map_err(MonteurError::Extract)?;
+            return deliver_prebuilt(&client, options, file, temp_dir_path, started);
+        }
+
+        // Source bundles are sometimes published as -sources.jar, so a .jar
+        // is only delivered as it is when it holds compiled classes
+        if let Some(file_name) = download.file_name.as_deref().filter(|name| {
+            format == ArchiveFormat::Zip && name.to_ascii_lowercase().ends_with(".jar")
+        }) {
+            if archive::is_compiled_jar(archive).map_err(MonteurError::Extract)? {
+                info!("The JAR holds compiled classes and no build files, delivering it as it is");
+                let file = archive::save_single_file(archive, file_name, temp_dir_path)
+                    .map_err(MonteurError::Extract)?;
+                return deliver_prebuilt(&client, options, file, temp_dir_path, started);
+            }
+            info!("The JAR is a source bundle, building it");
+        }
+
+        if options.no_flatten {
+            archive::extract_project(archive, format, &options.extract_only, temp_dir_path)
+                .map_err(MonteurError::Extract)?;
+            temp_dir_path.to_path_buf()
+        } else {
+            let project_root = archive::prepare_project(
+                archive,
+                format,
+                &options.extract_only,
+                temp_dir_path,
+                options.pick_latest_dir,
+            )
+            .map_err(MonteurError::Extract)?;
+            if options.unwrap_nested {
+                archive::unwrap_nested_archives(&project_root).map_err(MonteurError::Extract)?
+            } else {
+                project_root
+            }
+        }
+    };
+
+    build_project(&client, options, &project_root, incremental, started)
+}
+
+/// Result of building one project of a batch, see [`build_batch_from_url`].
+#[derive(Debug)]
+pub struct BatchProject {
+    /// Name of the project's directory or archive (without extension), which
+    /// its artifacts are copied to below the output directory
+    pub name: String,
+    pub outcome: Result<BuildOutcome, MonteurError>,
+}
+
+/// Downloads an archive of projects and builds each of its top-level
+/// directories and archives as a separate project, copying the artifacts of
+/// each to a subdirectory of the output directory named after it.
+///
+/// A project that fails doesn't stop the others; only failing to download or
+/// extract the outer archive is an error.
+pub fn build_batch_from_url(options: &BuildOptions) -> Result<Vec<BatchProject>, MonteurError> {
+    if options.git_ref.is_some() || git::is_git_url(&options.url) {
+        return Err(MonteurError::Download(anyhow!(
+            "Batch builds need an archive of projects, not a git repository"
+        )));
+    }
+    if options.incremental {
+        warn!("--incremental doesn't apply to batch builds, building anyway");
+    }
+    if options.skip_build_if_fresh {
+        warn!("--skip-build-if-fresh doesn't apply to batch builds, building anyway");
+    }
+    let options = &resolved_options(options)?;
+    let client = start_run(options)?;
+    let temp_dir_path = options.work_dir.as_path();
+
+    let download = download::download_archive(&client, options, temp_dir_path)
+        .map_err(MonteurError::Download)?;
+    if let (Some(signature_url), Some(pubkey)) = (&options.signature_url, &options.pubkey) {
+        download::verify_signature(&client, download.source(), signature_url, pubkey)
+            .map_err(MonteurError::Download)?;
+    }
+    let entries = archive::extract_batch(
+        download.source(),
+        download.format,
+        &options.extract_only,
+        temp_dir_path,
+    )
+    .map_err(MonteurError::Extract)?;
+
+    let mut projects = Vec::new();
+    for (index, entry) in entries.iter().enumerate() {
+        let started = Instant::now();
+        let file_name = entry
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let name = archive::strip_archive_extension(&file_name).to_string();
+        info!(
+            "Building project {} ({} of {})",
+            name,
+            index + 1,
+            entries.len()
+        );
+
+        let mut project_options = options.clone();
+        project_options.output_dir = options.output_dir.join(&name);
+        let project_root = if entry.is_dir() {
+            archive::batch_project_root(entry)
+        } else {
+            archive::extract_batch_project(entry, &entry.with_file_name(&name))
+        };
+        let outcome = project_root
+            .and_then(|project_root| {
+                if options.unwrap_nested {
+                    archive::unwrap_nested_archives(&project_root)
+                } else {
+                    Ok(project_root)
+                }
+            })
+            .map_err(MonteurError::Extract)
+            .and_then(|project_root| {
+                build_project(&client, &project_options, &project_root, None, started)
+            });
+        projects.push(BatchProject { name, outcome });
+    }
+    Ok(projects)
+}
+
+/// Returns a copy of the options with the URL of a `coordinate` filled in and
+/// the output directory made absolute, so that it keeps pointing at the same
+/// place wherever the build runs and the reported artifact paths are absolute.
+fn resolved_options(options: &BuildOptions) -> Result<BuildOptions, MonteurError> {
+    let mut options = options.clone();
+    if let Some(coordinate) = &options.coordinate {
+        options.url = download::coordinate_url(coordinate, &options.repository);
+        info!("Resolved {} to {}", coordinate, options.url);
+    }
+    options.output_dir = std::path::absolute(&options.output_dir)
+        .context(format!(
+            "Failed to resolve output directory {:?}",
+            options.output_dir
+        ))
+        .map_err(MonteurError::Io)?;
+    Ok(options)
+}
+
+/// Checks the output directory, creates the HTTP client and prepares an empty
+/// work directory.
+fn start_run(options: &BuildOptions) -> Result<Client, MonteurError> {
+    if options.insecure {
+        warn!(
+            "TLS certificate verification is disabled (--insecure), downloads can be intercepted or tampered with"
+        );
+    }
+
+    // Fail before the download if the artifacts couldn't be delivered anyway
+    fs_util::check_output_dir(&options.output_dir).map_err(MonteurError::Io)?;
+
+    // Shared by every request of the run; this also rejects malformed headers
+    // before anything is sent
+    let client = download::http_client(options).map_err(MonteurError::Download)?;
+
+    // Create the work directory, removing leftovers of a previous run
+    let temp_dir_path = options.work_dir.as_path();
+    if temp_dir_path.is_file() {
+        // Most likely unrelated to monteur, so don't delete it
+        return Err(MonteurError::Io(anyhow!(
+            "The work directory {:?} exists as a file; remove or rename it, or run monteur from another directory",
+            temp_dir_path
+        )));
+    }
+    if temp_dir_path.exists() {
+        fs::remove_dir_all(temp_dir_path)
+            .context("Failed to remove existing temp directory")
+            .map_err(MonteurError::Io)?;
+        info!("Removed existing temp directory");
+    }
+    fs::create_dir_all(temp_dir_path)
+        .context("Failed to create temp directory")
+        .map_err(MonteurError::Io)?;
+    info!("Created directory at: {}", temp_dir_path.display());
+    Ok(client)
+}
+
+/// Builds the extracted project at `project_root` and delivers its artifacts,
+/// recording them in the `incremental` state if given.
+fn build_project(
+    client: &Client,
+    options: &BuildOptions,
+    project_root: &Path,
+    incremental: Option<(PathBuf, IncrementalState)>,
+    started: Instant,
+) -> Result<BuildOutcome, MonteurError> {
+    let project_root = match &options.subdir {
+        Some(subdir) => {
+            let root = project_root.join(subdir);
+            if !root.is_dir() {
+                return Err(MonteurError::Extract(anyhow!(
+                    "--subdir {:?} is not a directory of the extracted project",
+                    subdir
+                )));
+            }
+            info!("Building in subdirectory: {}", subdir.display());
+            root
+        }
+        None => project_root.to_path_buf(),
+    };
+
+    // Child processes get the root as their working directory, so it must
+    // not depend on ours
+    let project_root = std::path::absolute(project_root)
+        .context("Failed to resolve project root")
+        .map_err(MonteurError::Io)?;
+
+    let build_system = if options.custom_build.is_some() {
+        BuildSystem::Custom
+    } else {
+        build_system::detect_build_system(&project_root).ok_or_else(|| {
+            // Docker-first repositories have no JVM artifact to produce, so
+            // don't suggest adding build files
+            if ["Dockerfile", "Containerfile"]
+                .iter()
+                .any(|file| project_root.join(file).is_file())
+            {
+                return MonteurError::Detection(anyhow!(
+                    "This looks like a Docker-based project (it has a Dockerfile but no Maven, Gradle, Pants or Buck2 build files); monteur builds JVM artifacts. If the project does produce a JAR, pass --custom-build and --custom-artifact-path."
+                ));
+            }
+            MonteurError::Detection(anyhow!(
+                "The project root {} {}. Make sure your project contains a pom.xml/pom.groovy/..., gradlew, pants.toml or .buckconfig file. If you're using Gradle but there is no gradlew file, run 'gradle wrapper' to generate one. For other build tools, pass --custom-build and --custom-artifact-path.",
+                project_root.display(),
+                fs_util::describe_dir(&project_root, DESCRIBED_ENTRIES)
+            ))
+        })?
+    };
+
+    if options.only_detect {
+        info!("Detected {:?}, skipping the build", build_system);
+        return outcome(build_system, Vec::new(), false, started);
+    }
+
+    // The project's own configuration fills in what the command line leaves open
+    let config_options;
+    let options = match project_config::load_project_config(&project_root)
+        .map_err(MonteurError::Build)?
+    {
+        Some(config) => {
+            config_options = project_config::apply_project_config(config, build_system, options);
+            &config_options
+        }
+        None => options,
+    };
+
+    // The project's JDK is looked up once, the builds read it from the options
+    let jdk_options;
+    let options = if options.project_jdk {
+        jdk_options = BuildOptions {
+            java_home: toolchain::project_jdk(&project_root, options),
+            ..options.clone()
+        };
+        &jdk_options
+    } else {
+        options
+    };
+
+    if options.warmup {
+        build_system::run_warmup(build_system, &project_root, options)
+            .map_err(MonteurError::Build)?;
+        info!("Downloaded the dependencies, skipping the build");
+        return outcome(build_system, Vec::new(), false, started);
+    }
+
+    if let Some((state_path, state)) = incremental.as_ref().filter(|_| options.skip_build_if_fresh)
+    {
+        let fresh = incremental::fresh_artifacts(state_path, state, build_system, &project_root)
+            .map_err(MonteurError::Io)?;
+        if let Some(artifacts) = fresh {
+            info!("No project file is newer than the artifacts of the last build, skipping it");
+            // Record the archive, so that --incremental recognizes it next time
+            let state = IncrementalState {
+                build_system: Some(build_system),
+                artifacts: artifacts.clone(),
+                ..state.clone()
+            };
+            incremental::save_incremental_state(state_path, &state).map_err(MonteurError::Io)?;
+            upload_artifacts(client, options, &artifacts)?;
+            return outcome(build_system, artifacts, true, started);
+        }
+    }
+
+    let artifact_path = match (build_system, options.artifact_kind) {
+        (BuildSystem::Custom, _) => options
+            .custom_artifact_path
+            .as_deref()
+            .unwrap_or(build_system.artifact_path()),
+        (_, ArtifactKind::Distribution) => build_system.distribution_path().ok_or_else(|| {
+            MonteurError::Artifact(anyhow!(
+                "--artifact-kind distribution is only supported for Gradle and custom builds"
+            ))
+        })?,
+        (BuildSystem::Buck2, ArtifactKind::Jar) if options.all_artifacts => {
+            return Err(MonteurError::Artifact(anyhow!(
+                "--all-artifacts is not supported for Buck2, select the outputs with --buck-target instead"
+            )));
+        }
+        (_, ArtifactKind::Jar) => build_system.artifact_path(),
+    };
+
+    // Only the selected module's target/ holds the artifacts to deliver, the
+    // others are built as its dependencies
+    let module_artifact_path;
+    let artifact_path = match (&options.maven_module, build_system) {
+        (Some(module), BuildSystem::Maven) => {
+            let module_dir = project::find_maven_module(&project_root, module)
+                .map_err(MonteurError::Artifact)?;
+            info!("Building Maven module {} in {:?}", module, module_dir);
+            module_artifact_path = Path::new(&module_dir)
+                .join(artifact_path)
+                .to_string_lossy()
+                .into_owned();
+            &module_artifact_path
+        }
+        (Some(_), _) => {
+            warn!("--maven-module only applies to Maven builds, ignoring it");
+            artifact_path
+        }
+        (None, _) => artifact_path,
+    };
+
+    // A root POM with pom packaging only aggregates modules or manages
+    // dependencies, so the JARs are in the target/ of its modules, if any
+    let pom_module_paths = match (build_system, &options.maven_module) {
+        (BuildSystem::Maven, None)
+            if !options.all_artifacts && options.artifact_kind == ArtifactKind::Jar =>
+        {
+            project::pom_module_artifact_paths(&project_root, artifact_path)
+        }
+        _ => None,
+    };
+    match &pom_module_paths {
+        Some(paths) if paths.is_empty() => {
+            return Err(MonteurError::Artifact(anyhow!(
+                "The root pom.xml has pom packaging and no modules, so it is a BOM or parent POM that doesn't build a JAR"
+            )));
+        }
+        Some(paths) => info!(
+            "The root pom.xml has pom packaging, searching the JARs of its modules in {}; use --maven-module or --all-artifacts to choose",
+            paths.join(", ")
+        ),
+        None => {}
+    }
+
+    let build = |options: &BuildOptions| {
+        match &options.custom_build {
+            Some(command) => {
+                build_system::run_custom_build(command, &project_root, options).map(|()| None)
+            }
+            None => build_system::run_build(build_system, &project_root, options),
+        }
+        .map_err(MonteurError::Build)
+    };
+    let reported_outputs = build(options)?;
+
+    let output_dir = options.output_dir.as_path();
+
+    let extensions = artifact::artifact_extensions(options);
+    let mut failed = Vec::new();
+    let copied = if options.artifact_kind == ArtifactKind::Distribution {
+        artifact::copy_distributions(
+            &project_root,
+            artifact_path,
+            output_dir,
+            !options.no_overwrite,
+        )
+        .map_err(MonteurError::Artifact)?
+    } else if options.all_artifacts {
+        // Pants collects the packages of all targets in the repository's
+        // dist/, one subdirectory per target, which stand in for the modules
+        let (search_root, artifact_path) = if build_system == BuildSystem::Pants {
+            (project_root.join(artifact_path), "")
+        } else {
+            (project_root.clone(), artifact_path)
+        };
+        let (copied, failures) = artifact::copy_all_artifacts(&search_root, artifact_path, options)
+            .map_err(MonteurError::Artifact)?;
+        failed = failures;
+        copied
+    } else {
+        let output_name = options.output_name_template.as_deref().and_then(|template| {
+            let info = project::read_project_info(build_system, &project_root);
+            match project::render_output_name(template, &info) {
+                Some(name) if fs_util::is_file_name(&name) => Some(name),
+                // The name and version come from the build files, which could
+                // contain e.g. "../"
+                Some(name) => {
+                    warn!(
+                        "--output-name-template rendered {:?}, which is not a valid file name, keeping the original file name",
+                        name
+                    );
+                    None
+                }
+                None => {
+                    warn!(
+                        "Could not determine the project name or version for --output-name-template, keeping the original file name"
+                    );
+                    None
+                }
+            }
+        });
+        let artifact_paths: Vec<_> = match &pom_module_paths {
+            Some(paths) => paths.iter().map(String::as_str).collect(),
+            None => vec![artifact_path],
+        }
+        .into_iter()
+        .chain(options.artifact_paths.iter().map(String::as_str))
+        .collect();
+        let searched = match &options.artifact_glob {
+            Some(glob) => glob.to_string(),
+            None => artifact_paths.join(", "),
+        };
+        let find_jar_files = |reported_outputs: Option<Vec<PathBuf>>| match (
+            &options.artifact_glob,
+            reported_outputs,
+        ) {
+            (Some(glob), _) => Ok(artifact::find_jars_matching(
+                &project_root,
+                glob,
+                &extensions,
+                options.artifact_search_depth,
+            )),
+            (None, Some(outputs)) => {
+                info!("Using the JAR files among the reported build outputs");
+                let mut jar_files: Vec<_> = outputs
+                    .into_iter()
+                    .filter(|path| artifact::has_extension(path, &extensions))
+                    .collect();
+                if artifact_paths.len() > 1 {
+                    jar_files.extend(artifact::find_jars(
+                        &project_root,
+                        &artifact_paths[1..],
+                        build_system,
+                        &extensions,
+                    )?);
+                }
+                Ok(jar_files)
+            }
+            (None, None) => {
+                artifact::find_jars(&project_root, &artifact_paths, build_system, &extensions)
+            }
+        };
+        let mut jar_files = find_jar_files(reported_outputs);
+        if options.retry_on_empty_artifact {
+            // A build that "succeeds" without producing anything is usually a
+            // glitch of a long-lived Gradle daemon, which a fresh build avoids
+            let reason = match &jar_files {
+                Ok(jar_files) if jar_files.is_empty() => {
+                    Some(format!("no JAR files in {}", searched))
+                }
+                Ok(_) => None,
+                Err(err) => Some(format!("{:#}", err)),
+            };
+            if let Some(reason) = reason {
+                warn!(
+                    "The build succeeded, but no artifact was found ({}); retrying once with a clean build",
+                    reason
+                );
+                let retry_options = BuildOptions {
+                    no_daemon: true,
+                    ..options.clone()
+                };
+                jar_files = find_jar_files(build(&retry_options)?);
+            }
+        }
+        let jar_files = jar_files.map_err(MonteurError::Artifact)?;
+        if options.inspect {
+            let candidates = artifact::select_artifact(jar_files, build_system, options)
+                .map_err(MonteurError::Artifact)?;
+            return Ok(inspected(build_system, &project_root, candidates, started));
+        }
+        vec![
+            artifact::copy_selected_artifact(
+                jar_files,
+                &searched,
+                build_system,
+                output_name.as_deref(),
+                options,
+            )
+            .map_err(MonteurError::Artifact)?,
+        ]
+    };
+
+    let include_pom = options.include_pom && build_system == BuildSystem::Maven;
+    if options.include_pom && !include_pom {
+        warn!("--include-pom only applies to Maven builds, ignoring it");
+    }
+    let artifacts = if options.output_format == OutputFormat::Jar {
+        let mut artifacts = copied
+            .iter()
+            .map(|artifact| artifact.path.clone())
+            .collect::<Vec<_>>();
+        if include_pom {
+            artifacts.extend(
+                artifact::copy_poms(&copied, !options.no_overwrite)
+                    .map_err(MonteurError::Artifact)?,
+            );
+        }
+        artifacts
+    } else {
+        let package = artifact::package_artifacts(
+            &copied,
+            include_pom,
+            options.output_format,
+            !options.no_overwrite,
+        )
+        .map_err(MonteurError::Artifact)?;
+        vec![package]
+    };
+
+    // Artifacts that failed to copy must be retried on the next run
+    if let Some((state_path, mut state)) = incremental.filter(|_| failed.is_empty()) {
+        state.build_system = Some(build_system);
+        state.artifacts = artifacts.clone();
+        incremental::save_incremental_state(&state_path, &state).map_err(MonteurError::Io)?;
+    }
+
+    upload_artifacts(client, options, &artifacts)?;
+    let mut outcome = outcome(build_system, artifacts, false, started)?;
+    outcome.failed = failed;
+    Ok(outcome)
+}
+
+/// Uploads the artifacts to `--upload-url`, if given.
+fn upload_artifacts(
+    client: &Client,
+    options: &BuildOptions,
+    artifacts: &[PathBuf],
+) -> Result<(), MonteurError> {
+    let Some(upload_url) = &options.upload_url else {
+        return Ok(());
+    };
+    if artifacts.len() > 1 && !upload_url.ends_with('/') {
+        return Err(MonteurError::Upload(anyhow!(
+            "Several artifacts were built, so the upload URL must end with '/' to have their file names appended"
+        )));
+    }
+    for path in artifacts {
+        download::upload_artifact(client, path, upload_url).map_err(MonteurError::Upload)?;
+    }
+    Ok(())
+}
+
+/// Copies a downloaded file that needs no build, such as a gzipped or plain
+/// JAR, to the output directory, or inspects it with `--inspect`. Nothing is
+/// copied with `--only-detect` or `--warmup`.
+fn deliver_prebuilt(
+    client: &Client,
+    options: &BuildOptions,
+    file: PathBuf,
+    temp_dir_path: &Path,
+    started: Instant,
+) -> Result<BuildOutcome, MonteurError> {
+    if options.only_detect {
+        return outcome(BuildSystem::Prebuilt, Vec::new(), false, started);
+    }
+    if options.warmup {
+        warn!("The download needs no build, so there are no dependencies to download");
+        return outcome(BuildSystem::Prebuilt, Vec::new(), false, started);
+    }
+    if options.inspect {
+        let candidate = CandidateArtifact {
+            size: fs::metadata(&file).map_or(0, |metadata| metadata.len()),
+            path: std::path::absolute(&file).unwrap_or(file),
+            selected: true,
+            excluded_reason: None,
+        };
+        return Ok(inspected(
+            BuildSystem::Prebuilt,
+            temp_dir_path,
+            vec![candidate],
+            started,
+        ));
+    }
+    let copied = artifact::copy_single_file(&file, &options.output_dir, !options.no_overwrite)
+        .map_err(MonteurError::Artifact)?;
+    let artifacts = vec![copied.path];
+    upload_artifacts(client, options, &artifacts)?;
+    outcome(BuildSystem::Prebuilt, artifacts, false, started)
+}
+
+/// Outcome of an `inspect` run, which doesn't deliver anything.
+fn inspected(
+    build_system: BuildSystem,
+    project_root: &Path,
+    candidates: Vec<CandidateArtifact>,
+    started: Instant,
+) -> BuildOutcome {
+    BuildOutcome {
+        build_system,
+        artifacts: Vec::new(),
+        failed: Vec::new(),
+        reused: false,
+        duration: started.elapsed(),
+        inspection: Some(Inspection {
+            project_root: std::path::absolute(project_root)
+                .unwrap_or_else(|_| project_root.to_path_buf()),
+            candidates,
+        }),
+    }
+}
+
+fn outcome(
+    build_system: BuildSystem,
+    artifacts: Vec<PathBuf>,
+    reused: bool,
+    started: Instant,
+) -> Result<BuildOutcome, MonteurError> {
+    let artifacts = artifacts
+        .into_iter()
+        .map(|path| {
+            let size = fs::metadata(&path)
+                .context(format!("Failed to stat {:?}", path))
+                .map_err(MonteurError::Io)?
+                .len();
+            Ok(Artifact { path, size })
+        })
+        .collect::<Result<Vec<_>, MonteurError>>()?;
+
+    Ok(BuildOutcome {
+        build_system,
+        artifacts,
+        failed: Vec::new(),
+        reused,
+        inspection: None,
+        duration: started.elapsed(),
+    })
+}
@@ -0,0 +1,105 @@
+use anyhow::{Context, Result};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+/// Writes status messages to stdout, or to stderr when stdout is reserved for
+/// machine-readable output. Warnings and errors always go to stderr.
+struct Logger {
+    status_to_stderr: bool,
+    console_level: LevelFilter,
+}
+
+/// The `--log-file`, which receives every message, including debug messages,
+/// together with the time since the start of the run.
+struct LogFile {
+    file: Mutex<File>,
+    started: Instant,
+}
+
+static LOG_FILE: OnceLock<LogFile> = OnceLock::new();
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // Debug and trace output of the HTTP stack is too noisy to be useful
+        metadata.level() <= log::max_level()
+            && (metadata.level() <= Level::Info || metadata.target().starts_with("monteur"))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        write_to_file(record.level().as_str(), &record.args().to_string());
+        if record.level() > self.console_level {
+            return;
+        }
+        match record.level() {
+            Level::Error => eprintln!("Error: {}", record.args()),
+            Level::Warn => eprintln!("Warning: {}", record.args()),
+            Level::Debug | Level::Trace => eprintln!("{}", record.args()),
+            _ if self.status_to_stderr => eprintln!("{}", record.args()),
+            _ => println!("{}", record.args()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Appends a message to the log file, if there is one. Failing to write it
+/// mustn't fail the run, so errors are ignored.
+fn write_to_file(level: &str, message: &str) {
+    if let Some(log_file) = LOG_FILE.get() {
+        let elapsed = log_file.started.elapsed().as_secs_f64();
+        let mut file = log_file.file.lock().unwrap();
+        let _ = writeln!(file, "[{:9.3}s] {:5} {}", elapsed, level, message);
+    }
+}
+
+/// Installs the logger. Must be called once, before anything is logged.
+/// `verbosity` is the number of `-v` flags: one enables debug messages, two
+/// also enable trace messages. Both go to stderr. With a `log_file`, every
+/// message is also appended to it, starting with the command line of the run.
+pub fn init(status_to_stderr: bool, verbosity: u8, log_file: Option<&Path>) -> Result<()> {
+    let console_level = match verbosity {
+        0 => LevelFilter::Info,
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    let mut max_level = console_level;
+    if let Some(path) = log_file {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context(format!("Failed to open log file {:?}", path))?;
+        let _ = LOG_FILE.set(LogFile {
+            file: Mutex::new(file),
+            started: Instant::now(),
+        });
+        max_level = max_level.max(LevelFilter::Debug);
+        let args: Vec<String> = std::env::args().collect();
+        write_to_file("INFO", &format!("Running {}", args.join(" ")));
+    }
+
+    log::set_boxed_logger(Box::new(Logger {
+        status_to_stderr,
+        console_level,
+    }))
+    .expect("logger must only be initialized once");
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+/// Records the final result of the run in the log file, if there is one; on
+/// the console, it's reported by the caller.
+pub fn finish<T>(result: &Result<T>) {
+    match result {
+        Ok(_) => write_to_file("INFO", "Run succeeded"),
+        Err(err) => write_to_file("ERROR", &format!("Run failed: {:#}", err)),
+    }
+}
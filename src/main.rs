@@ -1,257 +1,373 @@
+mod logger;
+
 use anyhow::{Context, Result};
-use flate2::read::GzDecoder;
-use std::env;
-use std::fs::{self, File};
-use std::io::copy;
-use std::path::{Path, PathBuf};
-use std::process::Command;
-use tar::Archive;
+use clap::{ArgAction, Parser};
+use log::{info, warn};
+use monteur::{
+    Artifact, BatchProject, BuildOptions, BuildOutcome, BuildSystem, CandidateArtifact,
+    FailedArtifact, MonteurError,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+/// Download, build and collect the artifact of a Java project.
+#[derive(Parser, Clone, Debug)]
+#[command(version, about)]
+struct Cli {
+    #[command(flatten)]
+    build: BuildOptions,
+
+    /// Print a JSON summary of the run on stdout; status messages go to stderr
+    #[arg(long, conflicts_with = "inspect")]
+    json: bool,
+
+    /// Print only the absolute path of the copied artifact on stdout, one line
+    /// per artifact; status messages go to stderr
+    #[arg(long, conflicts_with_all = ["json", "batch", "inspect", "only_detect", "warmup"])]
+    print_artifact_path: bool,
+
+    /// Attach a KEY=VALUE label to the JSON summary (repeatable)
+    #[arg(long = "label", value_name = "KEY=VALUE", value_parser = parse_label)]
+    labels: Vec<(String, String)>,
+
+    /// Build each top-level directory or archive in the downloaded archive as
+    /// a separate project, copying its artifacts to <output>/<project>/
+    #[arg(long, conflicts_with_all = ["git_ref", "incremental", "skip_build_if_fresh", "no_flatten", "inspect", "only_detect"])]
+    batch: bool,
+
+    /// Log more details on stderr; `-vv` also traces build system detection
+    #[arg(short, long, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Also append the messages of the run, including the build output, the
+    /// commands run and the result, to this file, with timings
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+}
 
-fn main() -> Result<()> {
-    // Get the download URL from the command line arguments
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: {} DOWNLOAD_URL", args[0]);
-        std::process::exit(1);
+/// Machine-readable summary printed with `--json`.
+#[derive(Serialize)]
+struct Summary {
+    build_system: BuildSystem,
+    artifacts: Vec<ArtifactSummary>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    failed: Vec<FailureSummary>,
+    duration_secs: f64,
+    labels: BTreeMap<String, String>,
+}
+
+/// Machine-readable summary printed with `--json --batch`.
+#[derive(Serialize)]
+struct BatchSummary {
+    projects: Vec<ProjectSummary>,
+    labels: BTreeMap<String, String>,
+}
+
+#[derive(Serialize)]
+struct ProjectSummary {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    build_system: Option<BuildSystem>,
+    artifacts: Vec<ArtifactSummary>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    failed: Vec<FailureSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_secs: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ArtifactSummary {
+    path: PathBuf,
+    size: u64,
+}
+
+impl From<Artifact> for ArtifactSummary {
+    fn from(artifact: Artifact) -> Self {
+        ArtifactSummary {
+            path: artifact.path,
+            size: artifact.size,
+        }
     }
-    let download_url = &args[1];
+}
 
-    // Create a "temp" directory in the current directory
-    let temp_dir_path = Path::new("temp");
-    if temp_dir_path.exists() {
-        fs::remove_dir_all(temp_dir_path).context("Failed to remove existing temp directory")?;
-        println!("Removed existing temp directory");
+/// Machine-readable result printed with `--only-detect --json`.
+#[derive(Serialize)]
+struct DetectSummary {
+    build_system: BuildSystem,
+    labels: BTreeMap<String, String>,
+}
+
+/// Version of the `--inspect` output, raised whenever a field changes in a way
+/// consumers could trip over.
+const INSPECT_SCHEMA_VERSION: u32 = 1;
+
+/// Machine-readable report printed with `--inspect`.
+#[derive(Serialize)]
+struct InspectSummary {
+    schema_version: u32,
+    build_system: BuildSystem,
+    project_root: PathBuf,
+    candidate_artifacts: Vec<CandidateSummary>,
+}
+
+#[derive(Serialize)]
+struct CandidateSummary {
+    path: PathBuf,
+    size: u64,
+    selected: bool,
+    excluded_reason: Option<String>,
+}
+
+impl From<CandidateArtifact> for CandidateSummary {
+    fn from(candidate: CandidateArtifact) -> Self {
+        CandidateSummary {
+            path: candidate.path,
+            size: candidate.size,
+            selected: candidate.selected,
+            excluded_reason: candidate.excluded_reason,
+        }
     }
-    fs::create_dir(temp_dir_path).context("Failed to create temp directory")?;
-    println!("Created directory at: {}", temp_dir_path.display());
+}
 
-    // Download the archive
-    println!("Downloading from: {}", download_url);
-    let response = reqwest::blocking::get(download_url).context("Failed to download file")?;
+#[derive(Serialize)]
+struct FailureSummary {
+    path: PathBuf,
+    error: String,
+}
 
-    if !response.status().is_success() {
-        anyhow::bail!("Failed to download file: HTTP status {}", response.status());
+impl From<FailedArtifact> for FailureSummary {
+    fn from(failure: FailedArtifact) -> Self {
+        FailureSummary {
+            path: failure.path,
+            error: failure.error,
+        }
     }
+}
+
+/// Set once SIGINT or SIGTERM was received.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    logger::init(
+        cli.json || cli.print_artifact_path || cli.build.inspect || cli.build.only_detect,
+        cli.verbose,
+        cli.log_file.as_deref(),
+    )?;
+
+    let result = run(&cli);
+    logger::finish(&result);
+    result
+}
 
-    // Create a temporary file to store the archive
-    let archive_path = temp_dir_path.join("archive.tar.gz");
-    let mut archive_file =
-        File::create(&archive_path).context("Failed to create temporary archive file")?;
-
-    // Save the downloaded content to the temporary file
-    copy(
-        &mut response
-            .bytes()
-            .context("Failed to read response")?
-            .as_ref(),
-        &mut archive_file,
-    )
-    .context("Failed to save archive")?;
-
-    // Extract the archive
-    println!("Extracting archive to: {}", temp_dir_path.display());
-    extract_tar_gz(&archive_path, temp_dir_path).context("Failed to extract archive")?;
-
-    // Move contents from top-level subfolder to temp directory
-    let entries = fs::read_dir(temp_dir_path)
-        .context("Failed to read temp directory")?
-        .collect::<Result<Vec<_>, _>>()
-        .context("Failed to collect directory entries")?;
-
-    // Find directories (excluding the archive file)
-    let subfolders: Vec<_> = entries
-        .iter()
-        .filter(|entry| {
-            let path = entry.path();
-            path.is_dir() && path.file_name() != Some(archive_path.file_name().unwrap_or_default())
-        })
-        .collect();
-
-    // If there's a single subfolder, move its contents up
-    if subfolders.len() == 1 {
-        let subfolder_path = &subfolders[0].path();
-        println!(
-            "Moving contents from subfolder: {}",
-            subfolder_path.display()
-        );
-
-        // Move all contents from subfolder to temp directory
-        let subfolder_entries = fs::read_dir(subfolder_path).context("Failed to read subfolder")?;
-
-        for entry in subfolder_entries {
-            let entry = entry.context("Failed to read subfolder entry")?;
-            let source_path = entry.path();
-            let file_name = source_path.file_name().unwrap();
-            let target_path = temp_dir_path.join(file_name);
-
-            // Move the file/directory
-            fs::rename(&source_path, &target_path).context(format!(
-                "Failed to move {:?} to {:?}",
-                source_path, target_path
-            ))?;
+/// Runs the build, or the batch of builds, described by the command line.
+fn run(cli: &Cli) -> Result<()> {
+    // Don't leave a running build or a half-populated work directory behind
+    // when interrupted
+    let work_dir = cli.build.work_dir.clone();
+    ctrlc::set_handler(move || {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        warn!("Interrupted, cleaning up");
+        monteur::terminate_build();
+        if let Err(err) = fs::remove_dir_all(&work_dir) {
+            warn!("Failed to remove {:?}: {}", work_dir, err);
         }
+        process::exit(130);
+    })
+    .context("Failed to install the interrupt handler")?;
 
-        // Remove the empty subfolder
-        fs::remove_dir(subfolder_path).context("Failed to remove empty subfolder")?;
-        println!("Successfully moved contents and removed subfolder");
+    if cli.batch {
+        let projects = unless_interrupted(monteur::build_batch_from_url(&cli.build))?;
+        return finish_batch(cli, projects);
     }
+    let outcome = unless_interrupted(monteur::build_from_url(&cli.build))?;
+    finish(cli, outcome)
+}
 
-    println!("Archive successfully extracted");
-
-    // cd into the temp directory
-    std::env::set_current_dir(temp_dir_path).context("Failed to set current directory")?;
-
-    // Detect build system based on presence of build files
-    let maven_patterns = [
-        "pom.xml",
-        "pom.atom",
-        "pom.clj",
-        "pom.groovy",
-        "pom.rb",
-        "pom.scala",
-        "pom.yaml",
-        "pom.yml",
-    ];
-
-    let is_maven = maven_patterns
-        .iter()
-        .any(|pattern| Path::new(pattern).exists());
-
-    let is_gradle = Path::new("gradlew").exists();
-
-    let artifact_path;
-
-    if is_maven {
-        println!("Using Maven");
-        // print the maven version by running "mvn version"
-        let output = Command::new("mvn")
-            .arg("--version")
-            .output()
-            .context("Failed to run mvn version")?;
-        println!("Maven version: {}", String::from_utf8_lossy(&output.stdout));
-
-        // run "mvn clean package -Dmaven.test.skip=true"
-        let output = Command::new("mvn")
-            .args(&["clean", "package", "-Dmaven.test.skip=true"])
-            .output()
-            .context("Failed to run mvn clean package")?;
-
-        // print the output
-        println!(
-            "Maven build output:\n{}",
-            String::from_utf8_lossy(&output.stdout)
-        );
-
-        artifact_path = "target/".to_string();
-    } else if is_gradle {
-        println!("Using Gradle");
-
-        // run "./gradlew clean build -x check -x test"
-        let output = Command::new("./gradlew")
-            .args(&["clean", "build", "-x", "check", "-x", "test"])
-            .output()
-            .context("Failed to run gradlew")?;
-
-        // print the output
-        println!(
-            "Gradle build output:\n{}",
-            String::from_utf8_lossy(&output.stdout)
-        );
-
-        artifact_path = "build/libs/".to_string();
-    } else {
-        anyhow::bail!(
-            "No build system detected. Make sure your project contains a pom.xml/pom.groovy/... or gradlew file. If you're using Gradle but there is no gradlew file, run 'gradle wrapper' to generate one."
-        );
+/// Passes on the result of a run, unless it failed because the build was
+/// terminated; then the interrupt handler is left to finish cleaning up and exit.
+fn unless_interrupted<T>(result: Result<T, MonteurError>) -> Result<T> {
+    match result {
+        Err(_) if INTERRUPTED.load(Ordering::SeqCst) => loop {
+            thread::park();
+        },
+        result => Ok(result?),
     }
+}
 
-    // collect the single .jar file with the longest name in the artifact_path folder
-    println!("Searching for JAR files in {}", artifact_path);
-
-    let jar_files = fs::read_dir(&artifact_path)
-        .context(format!("Failed to read directory: {}", artifact_path))?
-        .filter_map(|entry| {
-            let entry = entry.ok()?;
-            let path = entry.path();
-            if path.is_file() && path.extension()? == "jar" {
-                Some(path)
-            } else {
-                None
-            }
-        })
-        .collect::<Vec<_>>();
-
-    if jar_files.is_empty() {
-        anyhow::bail!("No JAR files found in {}", artifact_path);
+/// Reports the produced artifacts, on stdout as JSON if requested.
+fn finish(cli: &Cli, outcome: BuildOutcome) -> Result<()> {
+    if let Some(inspection) = outcome.inspection {
+        let summary = InspectSummary {
+            schema_version: INSPECT_SCHEMA_VERSION,
+            build_system: outcome.build_system,
+            project_root: inspection.project_root,
+            candidate_artifacts: inspection
+                .candidates
+                .into_iter()
+                .map(CandidateSummary::from)
+                .collect(),
+        };
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+        return Ok(());
     }
 
-    let jar_file = if is_maven {
-        // For Maven, follow the priority list:
-        // 1. If there is a jar that ends with -shaded.jar, use that
-        let shaded_jar = jar_files.iter().find(|path| {
-            path.file_name()
-                .unwrap_or_default()
-                .to_string_lossy()
-                .ends_with("-shaded.jar")
-        });
-
-        if let Some(jar) = shaded_jar {
-            println!("Found shaded JAR: {}", jar.display());
-            jar
+    if cli.build.only_detect {
+        if cli.json {
+            let summary = DetectSummary {
+                build_system: outcome.build_system,
+                labels: cli.labels.iter().cloned().collect(),
+            };
+            println!("{}", serde_json::to_string_pretty(&summary)?);
         } else {
-            // 2. If there is a jar that doesn't start with original-, use that
-            let non_original_jar = jar_files.iter().find(|path| {
-                !path
-                    .file_name()
-                    .unwrap_or_default()
-                    .to_string_lossy()
-                    .starts_with("original-")
-            });
-
-            if let Some(jar) = non_original_jar {
-                println!("Found default JAR: {}", jar.display());
-                jar
-            } else {
-                // 3. Use the .jar file with the longest name
-                println!("Using JAR with longest filename");
-                jar_files
-                    .iter()
-                    .max_by_key(|path| path.file_name().unwrap_or_default().to_string_lossy().len())
-                    .context("Failed to find JAR file")?
-            }
+            println!("{}", outcome.build_system);
         }
-    } else {
-        // For Gradle, use the original longest filename logic
-        jar_files
-            .iter()
-            .max_by_key(|path| path.file_name().unwrap_or_default().to_string_lossy().len())
-            .context("Failed to find JAR file")?
-    };
-
-    println!("Found JAR file: {}", jar_file.display());
-
-    // Create output directory if it doesn't exist
-    let output_dir = PathBuf::from("/output");
-    if !output_dir.exists() {
-        fs::create_dir_all(&output_dir).context("Failed to create output directory")?;
-        println!("Created output directory at: {}", output_dir.display());
+        return Ok(());
     }
 
-    // Copy the JAR file to the output directory
-    let file_name = jar_file.file_name().unwrap();
-    let output_path = output_dir.join(file_name);
+    let elapsed = outcome.duration;
+    if cli.build.warmup && !cli.json {
+        info!("Finished in {:.1}s", elapsed.as_secs_f64());
+        return Ok(());
+    }
+
+    let artifacts = outcome
+        .artifacts
+        .into_iter()
+        .map(ArtifactSummary::from)
+        .collect::<Vec<_>>();
 
-    fs::copy(jar_file, &output_path).context("Failed to copy JAR file to output directory")?;
-    println!("Copied JAR file to: {}", output_path.display());
+    match artifacts.as_slice() {
+        [artifact] => info!(
+            "Finished in {:.1}s: {} ({})",
+            elapsed.as_secs_f64(),
+            artifact.path.display(),
+            format_size(artifact.size)
+        ),
+        _ => info!(
+            "Finished in {:.1}s: {} artifacts ({})",
+            elapsed.as_secs_f64(),
+            artifacts.len(),
+            format_size(artifacts.iter().map(|artifact| artifact.size).sum())
+        ),
+    }
+
+    if cli.print_artifact_path {
+        for artifact in &artifacts {
+            println!("{}", artifact.path.display());
+        }
+    }
+    if cli.json {
+        let summary = Summary {
+            build_system: outcome.build_system,
+            artifacts,
+            failed: outcome
+                .failed
+                .into_iter()
+                .map(FailureSummary::from)
+                .collect(),
+            duration_secs: elapsed.as_secs_f64(),
+            labels: cli.labels.iter().cloned().collect(),
+        };
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+    }
     Ok(())
 }
 
-fn extract_tar_gz(archive_path: &PathBuf, dest_path: &Path) -> Result<()> {
-    let tar_gz = File::open(archive_path).context("Failed to open archive file")?;
-    let tar = GzDecoder::new(tar_gz);
-    let mut archive = Archive::new(tar);
-    archive
-        .unpack(dest_path)
-        .context("Failed to unpack archive")?;
+/// Reports the outcome of every project of a `--batch` run, on stdout as JSON
+/// if requested, and fails if any of them failed.
+fn finish_batch(cli: &Cli, projects: Vec<BatchProject>) -> Result<()> {
+    let total = projects.len();
+    let mut failed = 0;
+    let mut summaries = Vec::new();
+    for project in projects {
+        let summary = match project.outcome {
+            Ok(outcome) => {
+                info!(
+                    "{}: {} artifacts ({}) in {:.1}s",
+                    project.name,
+                    outcome.artifacts.len(),
+                    format_size(outcome.artifacts.iter().map(|artifact| artifact.size).sum()),
+                    outcome.duration.as_secs_f64()
+                );
+                ProjectSummary {
+                    name: project.name,
+                    build_system: Some(outcome.build_system),
+                    artifacts: outcome
+                        .artifacts
+                        .into_iter()
+                        .map(ArtifactSummary::from)
+                        .collect(),
+                    failed: outcome
+                        .failed
+                        .into_iter()
+                        .map(FailureSummary::from)
+                        .collect(),
+                    duration_secs: Some(outcome.duration.as_secs_f64()),
+                    error: None,
+                }
+            }
+            Err(err) => {
+                failed += 1;
+                let error = format!("{:#}", anyhow::Error::from(err));
+                warn!("{}: {}", project.name, error);
+                ProjectSummary {
+                    name: project.name,
+                    build_system: None,
+                    artifacts: Vec::new(),
+                    failed: Vec::new(),
+                    duration_secs: None,
+                    error: Some(error),
+                }
+            }
+        };
+        summaries.push(summary);
+    }
+    info!(
+        "Batch finished: {} of {} projects built",
+        total - failed,
+        total
+    );
+
+    if cli.json {
+        let summary = BatchSummary {
+            projects: summaries,
+            labels: cli.labels.iter().cloned().collect(),
+        };
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+    }
+    if failed > 0 {
+        anyhow::bail!("{} of {} projects failed to build", failed, total);
+    }
     Ok(())
 }
+
+/// Formats a byte count using binary units, e.g. `1.5 MiB`.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Parses a `KEY=VALUE` label given on the command line.
+fn parse_label(label: &str) -> Result<(String, String), String> {
+    match label.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got '{}'", label)),
+    }
+}
@@ -0,0 +1,229 @@
+use crate::BuildSystem;
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Name and version of the project, as far as they could be read from its
+/// build files.
+#[derive(Debug, Default)]
+pub(crate) struct ProjectInfo {
+    pub(crate) name: Option<String>,
+    pub(crate) version: Option<String>,
+    /// Maven `packaging`, if declared
+    pub(crate) packaging: Option<String>,
+    /// Maven modules, as the paths given in `modules`
+    pub(crate) modules: Vec<String>,
+}
+
+/// Reads the project name and version from the build files in `project_root`.
+pub(crate) fn read_project_info(build_system: BuildSystem, project_root: &Path) -> ProjectInfo {
+    match build_system {
+        BuildSystem::Maven => fs::read_to_string(project_root.join("pom.xml"))
+            .map(|pom| maven_project_info(&pom))
+            .unwrap_or_default(),
+        BuildSystem::Gradle => gradle_project_info(project_root),
+        BuildSystem::Pants | BuildSystem::Buck2 | BuildSystem::Custom | BuildSystem::Prebuilt => {
+            ProjectInfo::default()
+        }
+    }
+}
+
+/// Finds the directory, relative to `project_root`, of the Maven module with
+/// the given `artifactId` by reading the `pom.xml` files of the project.
+pub(crate) fn find_maven_module(project_root: &Path, artifact_id: &str) -> Result<PathBuf> {
+    let walker = WalkDir::new(project_root)
+        .max_depth(MAX_MODULE_DEPTH)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            // Skip hidden directories and build output
+            let name = entry.file_name().as_encoded_bytes();
+            entry.depth() == 0 || !(name.starts_with(b".") || name == b"target")
+        });
+    for entry in walker {
+        let entry = entry.context("Failed to walk project directory")?;
+        if entry.file_name() != "pom.xml" || !entry.file_type().is_file() {
+            continue;
+        }
+        let pom = fs::read_to_string(entry.path())
+            .context(format!("Failed to read {:?}", entry.path()))?;
+        if maven_project_info(&pom).name.as_deref() == Some(artifact_id) {
+            let module_dir = entry.path().parent().unwrap_or(project_root);
+            return Ok(module_dir
+                .strip_prefix(project_root)
+                .unwrap_or(module_dir)
+                .to_path_buf());
+        }
+    }
+    anyhow::bail!(
+        "No Maven module with artifactId {} found in the project",
+        artifact_id
+    )
+}
+
+/// Returns the artifact directories of the modules of a root POM with `pom`
+/// packaging, such as a parent or aggregator POM, whose own `target/` holds
+/// no JAR. Returns `None` for any other packaging, and an empty list for a
+/// BOM or parent POM without modules.
+pub(crate) fn pom_module_artifact_paths(
+    project_root: &Path,
+    artifact_path: &str,
+) -> Option<Vec<String>> {
+    let info = read_project_info(BuildSystem::Maven, project_root);
+    if info.packaging.as_deref() != Some("pom") {
+        return None;
+    }
+    let paths = info
+        .modules
+        .iter()
+        .map(|module| {
+            // A module may also be given as the path of its POM file
+            let module = Path::new(module);
+            let dir = if module.extension().is_some_and(|ext| ext == "xml") {
+                module.parent().unwrap_or(Path::new(""))
+            } else {
+                module
+            };
+            dir.join(artifact_path).to_string_lossy().into_owned()
+        })
+        .collect();
+    Some(paths)
+}
+
+/// Checks that a `pom.xml` looks like a Maven POM, i.e. its root element is
+/// `<project>`, rather than e.g. an unrelated XML test fixture or a file
+/// that isn't XML at all.
+pub(crate) fn is_maven_pom(pom: &str) -> bool {
+    let mut rest = pom.trim_start_matches('\u{feff}');
+    loop {
+        rest = rest.trim_start();
+        let Some(markup) = rest.strip_prefix('<') else {
+            return false;
+        };
+        // Skip the XML declaration, comments, processing instructions and
+        // the document type declaration in front of the root element; `end`
+        // is the index of the `>` closing them
+        let end = if let Some(comment) = markup.strip_prefix("!--") {
+            comment.find("-->").map(|end| "!--".len() + end + 2)
+        } else if markup.starts_with('?') || markup.starts_with('!') {
+            markup.find('>')
+        } else {
+            let name_end = markup
+                .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+                .unwrap_or(markup.len());
+            return &markup[..name_end] == "project";
+        };
+        let Some(end) = end else {
+            return false;
+        };
+        rest = &markup[end + 1..];
+    }
+}
+
+/// How deep below the project root `find_maven_module` looks for `pom.xml` files.
+const MAX_MODULE_DEPTH: usize = 6;
+
+/// Renders an `--output-name-template`, replacing `{name}` and `{version}`.
+/// Returns `None` if a placeholder used by the template is unknown.
+pub(crate) fn render_output_name(template: &str, info: &ProjectInfo) -> Option<String> {
+    let mut name = template.to_string();
+    for (placeholder, value) in [("{name}", &info.name), ("{version}", &info.version)] {
+        if name.contains(placeholder) {
+            name = name.replace(placeholder, value.as_deref()?);
+        }
+    }
+    Some(name)
+}
+
+/// Takes the `artifactId` and `version` of the project from a `pom.xml`,
+/// falling back to the parent's version. Values using properties such as
+/// `${revision}` can't be resolved and are ignored.
+fn maven_project_info(pom: &str) -> ProjectInfo {
+    let mut path: Vec<&str> = Vec::new();
+    let mut info = ProjectInfo::default();
+    let mut parent_version = None;
+
+    let mut rest = pom;
+    while let Some(start) = rest.find('<') {
+        let text = &rest[..start];
+        rest = &rest[start + 1..];
+
+        // Skip comments, processing instructions and CDATA sections
+        if let Some(comment) = rest.strip_prefix("!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[..end];
+        rest = &rest[end + 1..];
+        if tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+
+        if let Some(closing) = tag.strip_prefix('/') {
+            let value = Some(text.trim().to_string())
+                .filter(|value| !value.is_empty() && !value.contains("${"));
+            match (path.as_slice(), closing.trim()) {
+                (["project", "artifactId"], "artifactId") => info.name = value,
+                (["project", "version"], "version") => info.version = value,
+                (["project", "parent", "version"], "version") => parent_version = value,
+                (["project", "packaging"], "packaging") => info.packaging = value,
+                (["project", "modules", "module"], "module") => info.modules.extend(value),
+                _ => {}
+            }
+            path.pop();
+        } else if !tag.ends_with('/') {
+            path.push(tag.split_whitespace().next().unwrap_or_default());
+        }
+    }
+
+    info.version = info.version.or(parent_version);
+    info
+}
+
+/// Takes the version from `gradle.properties` or the build script and the
+/// name from `rootProject.name` in the settings.
+fn gradle_project_info(project_root: &Path) -> ProjectInfo {
+    let read = |file: &str| fs::read_to_string(project_root.join(file)).unwrap_or_default();
+
+    let version = read("gradle.properties")
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .find(|(key, _)| key.trim() == "version")
+        .map(|(_, value)| value.trim().to_string())
+        .or_else(|| {
+            ["build.gradle", "build.gradle.kts"]
+                .iter()
+                .find_map(|file| assignment_value(&read(file), "version"))
+        });
+    let name = ["settings.gradle", "settings.gradle.kts"]
+        .iter()
+        .find_map(|file| assignment_value(&read(file), "rootProject.name"));
+
+    ProjectInfo {
+        name,
+        version: version.filter(|version| !version.is_empty()),
+        ..ProjectInfo::default()
+    }
+}
+
+/// Finds a top-level `key = 'value'`, `key = "value"` or `key 'value'`
+/// statement in a Gradle script and returns the literal value.
+fn assignment_value(script: &str, key: &str) -> Option<String> {
+    script.lines().find_map(|line| {
+        let value = line.trim().strip_prefix(key)?;
+        if value.starts_with(|c: char| c.is_alphanumeric() || c == '_' || c == '.') {
+            // Only a longer key starting with the same name, e.g. versionCode
+            return None;
+        }
+        let value = value.trim_start();
+        let value = value.strip_prefix('=').unwrap_or(value).trim();
+        let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let value = &value[1..];
+        let value = &value[..value.find(quote)?];
+        (!value.contains('$')).then(|| value.to_string())
+    })
+}
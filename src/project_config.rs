@@ -0,0 +1,168 @@
+use crate::artifact::ArtifactKind;
+use crate::build_system::DEFAULT_MAVEN_COMMAND;
+use crate::{BuildOptions, BuildSystem};
+use anyhow::{Context, Result};
+use glob::Pattern;
+use log::{info, warn};
+use std::fs;
+use std::io;
+use std::path::Path;
+use toml_edit::{Document, Table};
+
+/// File in the project root with the project's own build configuration.
+pub(crate) const PROJECT_CONFIG_FILE: &str = ".monteur.toml";
+
+/// The build configuration a project ships in `.monteur.toml`:
+///
+/// ```toml
+/// goals = ["clean", "verify"]
+/// profiles = ["release"]
+/// artifact = "target/*-bundle.jar"
+/// ```
+#[derive(Debug)]
+pub(crate) struct ProjectConfig {
+    /// Maven goals or Gradle tasks to run instead of `clean package` or
+    /// `clean build`
+    goals: Vec<String>,
+    /// Maven profiles to activate
+    profiles: Vec<String>,
+    /// Glob, relative to the project root, matching the JAR to deliver
+    artifact: Option<Pattern>,
+}
+
+/// Reads the `.monteur.toml` in the project root, if there is one. Unknown
+/// keys are ignored with a warning, so that newer configurations still build.
+pub(crate) fn load_project_config(project_root: &Path) -> Result<Option<ProjectConfig>> {
+    let path = project_root.join(PROJECT_CONFIG_FILE);
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).context(format!("Failed to read {}", PROJECT_CONFIG_FILE)),
+    };
+    let document =
+        Document::parse(contents).context(format!("Failed to parse {}", PROJECT_CONFIG_FILE))?;
+    let table = document.as_table();
+
+    for (key, _) in table.iter() {
+        if !["goals", "profiles", "artifact"].contains(&key) {
+            warn!("Ignoring unknown key {:?} in {}", key, PROJECT_CONFIG_FILE);
+        }
+    }
+    let artifact = match table.get("artifact") {
+        Some(item) => {
+            let glob = item.as_str().with_context(|| {
+                format!("\"artifact\" in {} must be a string", PROJECT_CONFIG_FILE)
+            })?;
+            Some(Pattern::new(glob).context(format!(
+                "Invalid \"artifact\" glob in {}",
+                PROJECT_CONFIG_FILE
+            ))?)
+        }
+        None => None,
+    };
+    Ok(Some(ProjectConfig {
+        goals: string_array(table, "goals")?,
+        profiles: string_array(table, "profiles")?,
+        artifact,
+    }))
+}
+
+/// Reads an array of strings from the configuration, empty if it's missing.
+fn string_array(table: &Table, key: &str) -> Result<Vec<String>> {
+    let Some(item) = table.get(key) else {
+        return Ok(Vec::new());
+    };
+    item.as_array()
+        .and_then(|array| {
+            array
+                .iter()
+                .map(|value| value.as_str().map(str::to_string))
+                .collect::<Option<Vec<_>>>()
+        })
+        .with_context(|| {
+            format!(
+                "{:?} in {} must be an array of strings",
+                key, PROJECT_CONFIG_FILE
+            )
+        })
+}
+
+/// Returns the options to build with: those of the command line, with the
+/// settings of the project's configuration that they leave open.
+pub(crate) fn apply_project_config(
+    config: ProjectConfig,
+    build_system: BuildSystem,
+    options: &BuildOptions,
+) -> BuildOptions {
+    info!("Using the build configuration in {}", PROJECT_CONFIG_FILE);
+    let mut options = options.clone();
+
+    if let Some(artifact) = config.artifact {
+        if options.artifact_glob.is_some()
+            || options.all_artifacts
+            || options.artifact_kind != ArtifactKind::Jar
+            || !options.artifact_paths.is_empty()
+        {
+            info!(
+                "The artifact options on the command line override the artifact in {}",
+                PROJECT_CONFIG_FILE
+            );
+        } else {
+            info!(
+                "Selecting the artifact among the JARs matching {}",
+                artifact
+            );
+            options.artifact_glob = Some(artifact);
+        }
+    }
+
+    if config.goals.is_empty() && config.profiles.is_empty() {
+        return options;
+    }
+    match build_system {
+        BuildSystem::Maven if options.maven_command != DEFAULT_MAVEN_COMMAND => {
+            info!(
+                "--maven-command overrides the goals and profiles in {}",
+                PROJECT_CONFIG_FILE
+            );
+        }
+        BuildSystem::Maven => {
+            // Keep the placeholders of the default command, so that the other
+            // options still apply
+            let goals = match config.goals.as_slice() {
+                [] => vec!["clean".to_string(), "package".to_string()],
+                goals => goals.to_vec(),
+            };
+            let placeholders = DEFAULT_MAVEN_COMMAND
+                .split_whitespace()
+                .filter(|arg| arg.starts_with('{'))
+                .map(str::to_string);
+            let profiles =
+                (!config.profiles.is_empty()).then(|| format!("-P{}", config.profiles.join(",")));
+            options.maven_command = goals
+                .into_iter()
+                .chain(placeholders)
+                .chain(profiles)
+                .collect::<Vec<_>>()
+                .join(" ");
+            info!("Maven command template: {}", options.maven_command);
+        }
+        BuildSystem::Gradle => {
+            if !config.profiles.is_empty() {
+                warn!(
+                    "Gradle has no profiles, ignoring the profiles in {}",
+                    PROJECT_CONFIG_FILE
+                );
+            }
+            if !config.goals.is_empty() {
+                info!("Gradle tasks: {}", config.goals.join(" "));
+                options.gradle_tasks = config.goals;
+            }
+        }
+        _ => warn!(
+            "Goals and profiles only apply to Maven and Gradle builds, ignoring them in {}",
+            PROJECT_CONFIG_FILE
+        ),
+    }
+    options
+}
@@ -0,0 +1,111 @@
+use crate::BuildOptions;
+use log::{info, warn};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Reads the Java versions the project pins, with the file pinning them, from
+/// `.sdkmanrc` (`java=17.0.9-tem`) or `.tool-versions` (`java temurin-17.0.9+9`,
+/// possibly followed by fallback versions).
+fn pinned_java_versions(project_root: &Path) -> Option<(&'static str, Vec<String>)> {
+    if let Ok(sdkmanrc) = fs::read_to_string(project_root.join(".sdkmanrc")) {
+        let version = sdkmanrc.lines().find_map(|line| {
+            let (candidate, version) = line.split_once('=')?;
+            (candidate.trim() == "java").then(|| version.trim().to_string())
+        });
+        if let Some(version) = version.filter(|version| !version.is_empty()) {
+            return Some((".sdkmanrc", vec![version]));
+        }
+    }
+    if let Ok(tool_versions) = fs::read_to_string(project_root.join(".tool-versions")) {
+        let versions = tool_versions.lines().find_map(|line| {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            (fields.next() == Some("java")).then(|| fields.map(str::to_string).collect::<Vec<_>>())
+        });
+        if let Some(versions) = versions.filter(|versions| !versions.is_empty()) {
+            return Some((".tool-versions", versions));
+        }
+    }
+    None
+}
+
+/// Directories holding one installed JDK per subdirectory: the `--jdk-dir`s,
+/// then SDKMAN's and asdf's Java installations.
+fn jdk_dirs(options: &BuildOptions) -> Vec<PathBuf> {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let sdkman = env::var_os("SDKMAN_DIR")
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|home| home.join(".sdkman")));
+    let asdf = env::var_os("ASDF_DATA_DIR")
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|home| home.join(".asdf")));
+
+    let mut dirs = options.jdk_dirs.clone();
+    dirs.extend(sdkman.map(|sdkman| sdkman.join("candidates/java")));
+    dirs.extend(asdf.map(|asdf| asdf.join("installs/java")));
+    dirs
+}
+
+/// Finds the JDK for `version` in `dir`: the subdirectory named exactly like
+/// it, or else the last one, in name order, whose name starts with the version
+/// followed by a separator, so that `17` matches `17.0.9-tem`.
+fn find_jdk(dir: &Path, version: &str) -> Option<PathBuf> {
+    let is_jdk = |path: &Path| path.join("bin/java").is_file();
+    let exact = dir.join(version);
+    if is_jdk(&exact) {
+        return Some(exact);
+    }
+
+    let mut matches: Vec<PathBuf> = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry.file_name().to_str().is_some_and(|name| {
+                name.strip_prefix(version)
+                    .is_some_and(|rest| rest.starts_with(['.', '-', '+', '_']))
+            })
+        })
+        .map(|entry| entry.path())
+        .filter(|path| is_jdk(path))
+        .collect();
+    matches.sort();
+    matches.pop()
+}
+
+/// Locates the JDK pinned by the project in `.sdkmanrc` or `.tool-versions`.
+/// A pin without a matching installed JDK is reported, and the build then
+/// runs with the default JDK.
+pub(crate) fn project_jdk(project_root: &Path, options: &BuildOptions) -> Option<PathBuf> {
+    let Some((file, versions)) = pinned_java_versions(project_root) else {
+        info!("The project pins no Java version, building with the default JDK");
+        return None;
+    };
+
+    let dirs = jdk_dirs(options);
+    let found = versions.iter().find_map(|version| {
+        dirs.iter()
+            .find_map(|dir| find_jdk(dir, version))
+            .map(|jdk| (version, jdk))
+    });
+    match found {
+        Some((version, jdk)) => {
+            info!(
+                "Using JDK {} pinned in {}: {}",
+                version,
+                file,
+                jdk.display()
+            );
+            Some(jdk)
+        }
+        None => {
+            warn!(
+                "{} pins Java {}, but no matching JDK was found in {:?}, building with the default JDK",
+                file,
+                versions.join(" or "),
+                dirs
+            );
+            None
+        }
+    }
+}